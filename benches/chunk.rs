@@ -0,0 +1,124 @@
+//! Chunk storage benchmarks
+//!
+//! `ArrayChunk` mirrors the previous `[Option<BlockId>; CHUNK_SIZE]` layout
+//! so the palette storage can be compared against it.
+#![feature(test)]
+
+extern crate test;
+
+use sandbox_test::world::{
+    block::{BlockId, BlockRegistry},
+    chunk::{BlockSubPos, Chunk, CHUNK_SIZE},
+};
+use test::{black_box, Bencher};
+
+struct ArrayChunk {
+    data: Box<[Option<BlockId>; CHUNK_SIZE]>,
+}
+
+impl ArrayChunk {
+    fn from_fn(f: impl Fn(BlockSubPos) -> Option<BlockId>) -> Self {
+        let mut data = Box::new([None; CHUNK_SIZE]);
+        for (pos, _) in Chunk::empty().iter() {
+            data[index(pos)] = f(pos);
+        }
+        Self { data }
+    }
+}
+
+fn index(pos: BlockSubPos) -> usize {
+    let (x, y, z) = pos.into();
+    let w = Chunk::WIDTH;
+    x as usize + w * (z as usize + w * y as usize)
+}
+
+fn blocks() -> Vec<BlockId> {
+    let registry = BlockRegistry::from_ron(include_str!("../assets/blocks.ron")).unwrap();
+    registry.iter().map(|(id, _)| id).collect()
+}
+
+/// Terrain-like layout: a few solid layers of mixed blocks, air above
+fn terrain(blocks: &[BlockId]) -> impl Fn(BlockSubPos) -> Option<BlockId> + Send + Sync + '_ {
+    move |pos| {
+        let (x, y, z) = pos.into();
+        if (y as usize) < Chunk::HEIGHT / 2 {
+            Some(blocks[(x as usize + z as usize + y as usize) % blocks.len()])
+        } else {
+            None
+        }
+    }
+}
+
+fn positions() -> Vec<BlockSubPos> {
+    let mut seed = 0x2545_f491u32;
+    (0..4096)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let x = (seed % Chunk::WIDTH as u32) as u8;
+            let y = (seed / 16 % Chunk::HEIGHT as u32) as u8;
+            let z = (seed / 1024 % Chunk::WIDTH as u32) as u8;
+            BlockSubPos::new(x, y, z)
+        })
+        .collect()
+}
+
+#[bench]
+fn array_random_read(b: &mut Bencher) {
+    let blocks = blocks();
+    let chunk = ArrayChunk::from_fn(terrain(&blocks));
+    let positions = positions();
+    b.iter(|| {
+        positions
+            .iter()
+            .filter(|&&pos| chunk.data[index(pos)].is_some())
+            .count()
+    });
+}
+
+#[bench]
+fn palette_random_read(b: &mut Bencher) {
+    let blocks = blocks();
+    let chunk = Chunk::from_fn(terrain(&blocks));
+    let positions = positions();
+    b.iter(|| positions.iter().filter(|&&pos| chunk[pos].is_some()).count());
+}
+
+#[bench]
+fn array_iter(b: &mut Bencher) {
+    let blocks = blocks();
+    let chunk = ArrayChunk::from_fn(terrain(&blocks));
+    b.iter(|| chunk.data.iter().filter(|blk| blk.is_some()).count());
+}
+
+#[bench]
+fn palette_iter(b: &mut Bencher) {
+    let blocks = blocks();
+    let chunk = Chunk::from_fn(terrain(&blocks));
+    b.iter(|| chunk.iter().filter(|(_, blk)| blk.is_some()).count());
+}
+
+#[bench]
+fn array_write(b: &mut Bencher) {
+    let blocks = blocks();
+    let mut chunk = ArrayChunk::from_fn(terrain(&blocks));
+    let positions = positions();
+    b.iter(|| {
+        for (i, &pos) in positions.iter().enumerate() {
+            chunk.data[index(pos)] = Some(blocks[i % blocks.len()]);
+        }
+    });
+}
+
+#[bench]
+fn palette_write(b: &mut Bencher) {
+    let blocks = blocks();
+    let mut chunk = Chunk::from_fn(terrain(&blocks));
+    let positions = positions();
+    b.iter(|| {
+        for (i, &pos) in positions.iter().enumerate() {
+            chunk.set(pos, Some(blocks[i % blocks.len()]));
+        }
+    });
+}
@@ -2,13 +2,34 @@
 
 layout(location = 0) in vec3 v_color;
 layout(location = 1) in vec3 v_position;
-layout(location = 2) flat in uint v_picked;
+layout(location = 2) in vec3 v_block;
+layout(location = 3) flat in float v_alpha;
+layout(location = 4) in float v_ao;
+layout(location = 5) flat in int v_tile;
+layout(location = 6) in vec2 v_uv;
 layout(location = 0) out vec4 color;
 layout(location = 1) out vec3 normal;
 layout(location = 2) out vec3 position;
 
+layout(location = 2) uniform bool translucent;
+layout(location = 3) uniform sampler2D atlas;
+layout(location = 4) uniform uint atlas_columns;
+layout(binding = 0, std140) uniform picked { vec3 picked_position; };
+
 void main() {
-  color = vec4(v_color, float(v_picked));
+  float is_picked = float(floor(v_block) == picked_position);
+  vec3 base = v_color;
+  if (v_tile >= 0) {
+    // the atlas is uploaded bottom up, tiles are numbered from the top
+    uint tile = uint(v_tile);
+    vec2 cell = vec2(tile % atlas_columns, atlas_columns - 1u - tile / atlas_columns);
+    base *= texture(atlas, (cell + fract(v_uv)) / float(atlas_columns)).rgb;
+  }
+  // fully occluded corners keep 40% of their color
+  vec3 shaded = base * mix(0.4, 1.0, v_ao);
+  // the alpha channel marks the picked block, except for the blended draw
+  // of translucent faces where it is the opacity
+  color = vec4(shaded, translucent ? v_alpha : is_picked);
   normal = normalize(cross(dFdx(v_position), dFdy(v_position)));
   position = v_position.xyz;
 }
@@ -3,14 +3,29 @@
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec3 color;
 layout(location = 2) in uint face;
+layout(location = 3) in vec2 light;
+layout(location = 4) in float alpha;
+layout(location = 5) in float height;
+layout(location = 6) in vec3 extent;
+layout(location = 7) in vec4 ao;
+layout(location = 8) in int tile;
 layout(location = 0) out vec3 gcolor;
 layout(location = 1) out uint gface;
-layout(location = 2) out uint gpicked;
-layout(binding = 0, std140) uniform picked { vec3 picked_position; };
+layout(location = 2) out float galpha;
+layout(location = 3) out float gheight;
+layout(location = 4) out vec3 gextent;
+layout(location = 5) out vec4 gao;
+layout(location = 6) out int gtile;
 
 void main() {
-  gpicked = uint(position == picked_position);
-  gcolor = color;
+  // each missing light level dims the face by 20%, with some ambient left
+  float level = max(light.x, light.y) * 15.0;
+  gcolor = color * max(pow(0.8, 15.0 - level), 0.05);
   gface = face;
+  galpha = alpha;
+  gheight = height;
+  gextent = extent;
+  gao = ao;
+  gtile = tile;
   gl_Position = vec4(position, 0.0);
-}
\ No newline at end of file
+}
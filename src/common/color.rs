@@ -1,7 +1,9 @@
 use std::fmt;
 
+use serde::Deserialize;
+
 /// Predefined color
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
 pub struct Color(u8, u8, u8);
 
 impl fmt::Display for Color {
@@ -23,6 +23,24 @@ impl Direction {
         };
         arr[is_positive as usize]
     }
+
+    pub fn axis(self) -> Axis {
+        use Direction::*;
+        match self {
+            East | West => Axis::X,
+            Up | Down => Axis::Y,
+            North | South => Axis::Z,
+        }
+    }
+
+    pub fn is_positive(self) -> bool {
+        use Direction::*;
+        matches!(self, South | East | Up)
+    }
+
+    pub fn opposite(self) -> Self {
+        Self::from_axis(self.axis(), !self.is_positive())
+    }
 }
 
 impl Into<glam::IVec3> for Direction {
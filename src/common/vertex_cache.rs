@@ -24,6 +24,10 @@ where
         }
     }
 
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
     pub fn as_slice(&self) -> VertexBufferSlice<'_, T> {
         let count = self.count;
         self.buffer.slice(..count).unwrap()
@@ -0,0 +1,5 @@
+use crate::world::block::BlockId;
+
+/// Block that found no room where it landed, left lying around instead
+#[derive(Debug, Clone, Copy)]
+pub struct DroppedItem(pub BlockId);
@@ -0,0 +1,5 @@
+use crate::world::block::BlockId;
+
+/// Block taken out of the map while it falls, put back where it lands
+#[derive(Debug, Clone, Copy)]
+pub struct FallingBlock(pub BlockId);
@@ -1,3 +1,5 @@
+mod dropped_item;
+mod falling_block;
 mod head_pitch;
 mod model_structure;
 mod position;
@@ -7,6 +9,8 @@ mod sprite;
 mod user_control;
 mod velocity;
 
+pub use dropped_item::DroppedItem;
+pub use falling_block::FallingBlock;
 pub use head_pitch::HeadPitch;
 pub use model_structure::ModelStructure;
 pub use position::Position;
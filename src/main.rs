@@ -1,4 +1,5 @@
-use bevy_app::{App, Plugin};
+use anyhow::Context;
+use bevy_app::{App, CoreStage, EventReader, Plugin};
 use bevy_core::CorePlugin;
 use bevy_diagnostic::{
     DiagnosticsPlugin, EntityCountDiagnosticsPlugin, FrameTimeDiagnosticsPlugin,
@@ -9,13 +10,42 @@ use sandbox_test as lib;
 use lib::{
     components::{EntityBundle, ModelStructure, ReceiveGravity, UserControl},
     pipeline, plugins,
-    renderer::{self, pass::*, RenderPlugin},
+    renderer::{self, atlas::TextureAtlas, pass::*, Action, RenderPlugin},
+    world::{block::BlockRegistry, config::WorldConfig, Map},
 };
 
+static BLOCKS_PATH: &str = "assets/blocks.ron";
+static TEXTURES_PATH: &str = "assets/textures";
+static WORLD_PATH: &str = "world.sav";
+static WORLD_CONFIG_PATH: &str = "assets/world.ron";
+
+/// `[--config <path>] [--seed <seed>]`
+fn world_config() -> anyhow::Result<WorldConfig> {
+    let mut path = WORLD_CONFIG_PATH.to_string();
+    let mut seed = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow::anyhow!("missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--config" => path = value()?,
+            "--seed" => seed = Some(value()?.parse::<u32>().context("invalid seed")?),
+            _ => anyhow::bail!("unknown argument {:?}", arg),
+        }
+    }
+    let mut config = WorldConfig::load(&path)?;
+    if let Some(seed) = seed {
+        config.seed = seed;
+    }
+    Ok(config)
+}
+
 fn startup(mut commands: Commands) {
     commands
         .spawn_bundle(EntityBundle {
-            position: (5.0, 70.0, 5.0).into(),
+            position: (5.0, 110.0, 5.0).into(),
             velocity: (0.0, 0.0, 0.0).into(),
             rotation: Default::default(),
             head_pitch: Default::default(),
@@ -27,12 +57,32 @@ fn startup(mut commands: Commands) {
         })
         .insert_bundle((ReceiveGravity, UserControl::default()));
 }
+
+fn save_world_system(
+    mut action_reader: EventReader<Action>,
+    map: Res<Map>,
+    registry: Res<BlockRegistry>,
+) {
+    for action in action_reader.iter() {
+        if let Action::Exit = action {
+            match map.save(WORLD_PATH, &registry) {
+                Ok(()) => log::info!("world saved to {}", WORLD_PATH),
+                Err(err) => log::error!("{:?}", err),
+            }
+        }
+    }
+}
 struct GamePlugin;
 
 impl Plugin for GamePlugin {
     fn build(&self, appb: &mut bevy_app::AppBuilder) {
+        let registry = BlockRegistry::load(BLOCKS_PATH).expect("failed to load blocks");
+        let atlas = TextureAtlas::load(TEXTURES_PATH).expect("failed to load textures");
         let control = lib::resources::ControlConfig {
             rotation_scale: glam::vec2(0.01, 0.005),
+            place_block: registry.lookup("yellow").unwrap(),
+            light_block: registry.lookup("lamp").unwrap(),
+            fluid_block: registry.lookup("water").unwrap(),
         };
         let camera = renderer::camera::Camera {
             eye: glam::vec3a(15.0, 5.0, 15.0),
@@ -42,17 +92,29 @@ impl Plugin for GamePlugin {
             hard_range: 0.1..64.0,
             soft_range: 0.0..64.0,
         };
-        let map = lib::world::Map::new(
-            (4, 4),
-            lib::world::generator::noise::NoiseGenerator::new(
-                noise::ScalePoint::new(noise::HybridMulti::new()).set_scale(0.03),
-                noise::ScalePoint::new(noise::Worley::new()).set_scale(0.07),
-            ),
-        );
+        let config = world_config().expect("failed to load world config");
+        log::info!("world seed {}", config.seed);
+        let generator = config
+            .build_generator(&registry)
+            .expect("failed to create world generator");
+        let structures = config
+            .build_structures(&registry)
+            .expect("failed to create world structures");
+        let map = if std::path::Path::new(WORLD_PATH).exists() {
+            Map::load(WORLD_PATH, &registry, generator, config.seed).expect("failed to load world")
+        } else {
+            Map::new(generator).with_seed(config.seed)
+        }
+        .with_structures(structures);
+        let random_ticks = plugins::RandomTicks::new(config.seed as u64, 3);
         appb.insert_resource(camera)
+            .insert_resource(random_ticks)
+            .insert_resource(registry)
+            .insert_resource(atlas)
             .insert_resource(control)
             .insert_resource(map)
-            .add_startup_system(startup.system());
+            .add_startup_system(startup.system())
+            .add_system_to_stage(CoreStage::Last, save_world_system.system());
     }
 }
 
@@ -70,6 +132,7 @@ pub fn main() {
         .add_plugin(FrameTimeDiagnosticsPlugin)
         .add_plugin(EntityCountDiagnosticsPlugin)
         .add_plugin(plugins::UiPlugin)
+        .add_plugin(plugins::WorldStreamingPlugin)
         .add_plugin(plugins::PhysicsPlugin)
         .add_plugin(plugins::UserInputPlugin)
         .add_plugin(RenderPlugin::<
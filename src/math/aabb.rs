@@ -61,6 +61,11 @@ impl AABB {
         position + glam::vec3a(extent3d.x / 2.0, extent3d.y / 2.0, extent3d.x / 2.0)
     }
 
+    /// Squared distance from the point to the nearest point in the box
+    pub fn distance_squared(self, point: glam::Vec3A) -> f32 {
+        point.max(self.min()).min(self.max()).distance_squared(point)
+    }
+
     pub fn split_y(self) -> (Self, Self) {
         let Self { position, extent3d } = self;
         let extent3d = extent3d * glam::vec3a(1.0, 0.5, 1.0);
@@ -148,14 +153,14 @@ impl AABB {
         ret
     }
 
-    pub fn iter_pos(self) -> impl Iterator<Item = glam::UVec3> {
+    pub fn iter_pos(self) -> impl Iterator<Item = glam::IVec3> {
         let Self { position, extent3d } = self;
-        let (minx, miny, minz) = position.floor().max(glam::Vec3A::default()).into();
+        let (minx, miny, minz) = position.floor().into();
         let (maxx, maxy, maxz) = (position + extent3d).ceil().into();
-        let (minx, miny, minz) = (minx as u32, miny as u32, minz as u32);
-        let (maxx, maxy, maxz) = (maxx as u32, maxy as u32, maxz as u32);
+        let (minx, miny, minz) = (minx as i32, miny as i32, minz as i32);
+        let (maxx, maxy, maxz) = (maxx as i32, maxy as i32, maxz as i32);
         itertools::iproduct!(minx..maxx, miny..maxy, minz..maxz)
-            .map(|(x, y, z)| glam::uvec3(x, y, z))
+            .map(|(x, y, z)| glam::ivec3(x, y, z))
     }
 }
 
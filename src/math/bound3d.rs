@@ -2,7 +2,7 @@ use std::ops::{BitAnd, BitAndAssign, Range, RangeFrom, RangeFull, RangeTo};
 
 use strum::IntoEnumIterator;
 
-use crate::world::{chunk::Chunk, MapSize};
+use crate::world::WORLD_HEIGHT;
 
 use super::axis::{Axis, ExtractAxis, HasAxis, HasAxisMutExt, MapAxisExt};
 
@@ -115,11 +115,11 @@ impl Bound3D {
         max: glam::const_vec3a!([f32::INFINITY, f32::INFINITY, f32::INFINITY]),
     };
 
-    pub fn from_world(size: &MapSize) -> Self {
-        let xr = ((size.width() as usize) * Chunk::WIDTH) as f32;
-        let yr = Chunk::HEIGHT as f32;
-        let zr = ((size.height() as usize) * Chunk::WIDTH) as f32;
-        Self::from([0.0..xr, 0.0..yr, 0.0..zr])
+    /// World is only bounded vertically
+    pub fn from_world() -> Self {
+        let yr = WORLD_HEIGHT as f32;
+        let inf = f32::NEG_INFINITY..f32::INFINITY;
+        Self::from([inf.clone(), 0.0..yr, inf])
     }
 
     pub fn merge_axis(&mut self, axis: Axis, rhs: &Self) {
@@ -0,0 +1,111 @@
+use super::aabb::AABB;
+
+/// Volume seen by a camera, six planes with normals pointing inwards
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    /// `xyz` is the unit normal, `w` the offset, so `dot(normal, p) + w` is
+    /// the signed distance of `p`
+    planes: [glam::Vec4; 6],
+}
+
+impl Frustum {
+    /// Planes of a `perspective * view_model` matrix
+    pub fn from_matrix(matrix: glam::Mat4) -> Self {
+        let row = |index| matrix.row(index);
+        let mut planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(3) + row(2),
+            row(3) - row(2),
+        ];
+        for plane in planes.iter_mut() {
+            *plane /= plane.truncate().length();
+        }
+        Self { planes }
+    }
+
+    pub fn contains(&self, point: glam::Vec3A) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(point.into()) + plane.w >= 0.0)
+    }
+
+    /// AABB is at least partly inside, may give false positives near the
+    /// corners of the frustum
+    pub fn intersects(&self, aabb: AABB) -> bool {
+        let (min, max) = (aabb.min(), aabb.max());
+        self.planes.iter().all(|plane| {
+            // corner furthest along the normal
+            let normal = plane.truncate();
+            let corner = glam::vec3(
+                if normal.x >= 0.0 { max.x } else { min.x },
+                if normal.y >= 0.0 { max.y } else { min.y },
+                if normal.z >= 0.0 { max.z } else { min.z },
+            );
+            normal.dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Looking down -z from the origin, 90 degrees, 1..100
+    fn frustum() -> Frustum {
+        let perspective = glam::Mat4::perspective_rh_gl(90f32.to_radians(), 1.0, 1.0, 100.0);
+        Frustum::from_matrix(perspective)
+    }
+
+    fn cube(x: f32, y: f32, z: f32) -> AABB {
+        AABB::from_block_pos(glam::vec3a(x, y, z))
+    }
+
+    #[test]
+    fn test_contains() {
+        let frustum = frustum();
+        assert!(frustum.contains(glam::vec3a(0.0, 0.0, -10.0)));
+        assert!(frustum.contains(glam::vec3a(9.0, -9.0, -10.0)));
+        assert!(!frustum.contains(glam::vec3a(11.0, 0.0, -10.0)));
+        assert!(!frustum.contains(glam::vec3a(0.0, 0.0, 10.0)));
+        // near and far planes
+        assert!(!frustum.contains(glam::vec3a(0.0, 0.0, -0.5)));
+        assert!(!frustum.contains(glam::vec3a(0.0, 0.0, -101.0)));
+    }
+
+    #[test]
+    fn test_intersects() {
+        let frustum = frustum();
+        assert!(frustum.intersects(cube(0.0, 0.0, -10.0)));
+        // behind, beyond the far plane and off to the side
+        assert!(!frustum.intersects(cube(0.0, 0.0, 5.0)));
+        assert!(!frustum.intersects(cube(0.0, 0.0, -110.0)));
+        assert!(!frustum.intersects(cube(20.0, 0.0, -10.0)));
+        // straddling the side plane
+        assert!(frustum.intersects(cube(9.5, 0.0, -10.0)));
+        // bigger than the frustum
+        let huge = AABB::from_ab(
+            glam::vec3a(-500.0, -500.0, -500.0),
+            glam::Vec3A::splat(500.0),
+        );
+        assert!(frustum.intersects(huge));
+    }
+
+    #[test]
+    fn test_view_model() {
+        let camera = crate::renderer::camera::Camera {
+            eye: glam::vec3a(0.0, 0.0, 0.0),
+            yaw: std::f32::consts::FRAC_PI_2,
+            pitch: 0.0,
+            fov: 90f32.to_radians(),
+            hard_range: 0.1..64.0,
+            soft_range: 0.0..64.0,
+        };
+        let frustum = camera.frustum(1.0);
+        let direction = camera.get_direction() * 10.0;
+        assert!(frustum.contains(direction));
+        assert!(!frustum.contains(-direction));
+    }
+}
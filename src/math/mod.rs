@@ -1,5 +1,6 @@
 pub mod aabb;
 pub mod axis;
 pub mod bound3d;
+pub mod frustum;
 pub mod trit;
 pub mod voxel_bound;
@@ -1,7 +1,9 @@
 mod physics_simulation;
 pub mod ui;
 mod user_control_system;
+mod world_streaming;
 
 pub use physics_simulation::*;
 pub use ui::*;
 pub use user_control_system::*;
+pub use world_streaming::*;
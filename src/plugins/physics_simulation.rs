@@ -1,21 +1,26 @@
 use std::ops::Add;
 
-use bevy_app::{CoreStage, Plugin};
+use bevy_app::{CoreStage, EventWriter, Plugin};
 use bevy_core::{Time, Timer};
 use bevy_ecs::{prelude::*, schedule::ShouldRun};
 
 use crate::{
     components::{
-        HeadPitch, ModelStructure, Position, ReceiveGravity, Rotation, Sprite, UserControl,
-        Velocity,
+        DroppedItem, FallingBlock, HeadPitch, ModelStructure, Position, ReceiveGravity, Rotation,
+        Sprite, UserControl, Velocity,
     },
     math::{
         aabb::{IntoAABB, AABB},
-        axis::{ExtractAxis, HasAxis, HasAxisMut, MapAxisExt, SortAxisExt},
+        axis::{Axis, ExtractAxis, HasAxis, HasAxisMut, MapAxisExt, SortAxisExt},
         bound3d::{Bound3D, LimitRange},
         voxel_bound::VoxelBound,
     },
-    world::Map,
+    world::{
+        block::{BlockId, BlockRegistry, Collision},
+        block_neighbors,
+        update::{BlockChanged, BlockTicks, TickRng},
+        ChunkPos, Map,
+    },
 };
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -44,19 +49,64 @@ impl From<[f32; 3]> for PhysicsPosition {
 impl HasAxis for PhysicsPosition {
     type Target = f32;
 
-    fn get_axis(&self, axis: crate::math::axis::Axis) -> &Self::Target {
+    fn get_axis(&self, axis: Axis) -> &Self::Target {
         self.0.get_axis(axis)
     }
 }
 
 impl HasAxisMut for PhysicsPosition {
-    fn get_axis_mut(&mut self, axis: crate::math::axis::Axis) -> &mut Self::Target {
+    fn get_axis_mut(&mut self, axis: Axis) -> &mut Self::Target {
         self.0.get_axis_mut(axis)
     }
 }
 
+/// Block at `target` stops an entity at `position` moving by `velocity`
+/// along `axis`, chunks that are not generated yet (`None`) count as solid
+fn is_blocking(
+    registry: &BlockRegistry,
+    block: Option<BlockId>,
+    target: glam::Vec3A,
+    position: glam::Vec3A,
+    axis: Axis,
+    velocity: f32,
+) -> bool {
+    let block = match block {
+        Some(block) => block,
+        None => return true,
+    };
+    match registry[block].collision {
+        Collision::Solid => true,
+        Collision::Passable | Collision::Climbable => false,
+        // only when falling from above the top face
+        Collision::Platform => {
+            axis == Axis::Y && velocity < 0.0 && position.y >= target.y + 1.0 - 0.01
+        }
+    }
+}
+
 struct PhysicsTimer(Timer);
 
+/// Random block ticks done in every loaded chunk per physics tick
+pub struct RandomTicks {
+    pub per_chunk: u32,
+    pub rng: TickRng,
+}
+
+impl RandomTicks {
+    pub fn new(seed: u64, per_chunk: u32) -> Self {
+        Self {
+            per_chunk,
+            rng: TickRng::new(seed),
+        }
+    }
+}
+
+impl Default for RandomTicks {
+    fn default() -> Self {
+        Self::new(0, 3)
+    }
+}
+
 fn physics_tick_system(time: Res<Time>, mut timer: ResMut<PhysicsTimer>) -> ShouldRun {
     if timer.0.tick(time.delta()).just_finished() {
         ShouldRun::Yes
@@ -66,7 +116,6 @@ fn physics_tick_system(time: Res<Time>, mut timer: ResMut<PhysicsTimer>) -> Shou
 }
 
 fn sync_position_system(
-    map: Res<Map>,
     timer: Res<PhysicsTimer>,
     mut has_physics_position: Query<(&mut Position, &Bound3D, &Velocity, &PhysicsPosition)>,
     no_physics_position: Query<(Entity, &Position), Without<PhysicsPosition>>,
@@ -79,7 +128,7 @@ fn sync_position_system(
     for (entity, &pos) in no_physics_position.iter() {
         commands
             .entity(entity)
-            .insert_bundle((PhysicsPosition::from(pos), Bound3D::from_world(&map.size())));
+            .insert_bundle((PhysicsPosition::from(pos), Bound3D::from_world()));
     }
 }
 
@@ -108,38 +157,104 @@ fn player_velocity_system(
     }
 }
 
-fn gravity_system(mut query: Query<&mut Velocity, With<ReceiveGravity>>) {
-    for mut vel in query.iter_mut() {
-        vel.0 += glam::vec3a(0.0, -0.01, 0.0);
+const GRAVITY: f32 = 0.01;
+/// Upward push on an entity with its middle inside fluid, slightly above
+/// gravity so entities float up
+const BUOYANCY: f32 = 0.012;
+/// Velocity kept per tick while touching fluid
+const FLUID_DRAG: f32 = 0.8;
+
+/// Entity that can be inside fluid, falling blocks sink
+type Body<'a> = (
+    &'a PhysicsPosition,
+    &'a ModelStructure,
+    Option<&'a FallingBlock>,
+);
+
+fn gravity_system(
+    map: Res<Map>,
+    registry: Res<BlockRegistry>,
+    mut query: Query<(&mut Velocity, Option<Body>), With<ReceiveGravity>>,
+) {
+    for (mut vel, body) in query.iter_mut() {
+        let (pos, structure, falling) = match body {
+            Some((pos, structure, falling)) => (pos.0, structure, falling),
+            None => {
+                vel.0.y -= GRAVITY;
+                continue;
+            }
+        };
+        let touching = map
+            .scan_aabb(structure.into_aabb(pos))
+            .any(|(_, blk)| match blk {
+                Some(blk) => registry[blk].data.is_fluid(),
+                None => false,
+            });
+        if !touching {
+            vel.0.y -= GRAVITY;
+            continue;
+        }
+        let middle = pos + glam::vec3a(0.0, structure.height / 2.0, 0.0);
+        let middle = glam::IVec3::new(
+            middle.x.floor() as i32,
+            middle.y.floor() as i32,
+            middle.z.floor() as i32,
+        );
+        vel.0 *= FLUID_DRAG;
+        vel.0.y -= GRAVITY;
+        if falling.is_none() && map.fluid_at(&registry, middle).is_some() {
+            vel.0.y += BUOYANCY;
+        }
     }
 }
 
 fn sprite_collision_system(
     map: Res<Map>,
-    mut query: Query<(Entity, &mut PhysicsPosition, &Velocity, &Sprite)>,
+    registry: Res<BlockRegistry>,
+    // dropped items rest on blocks instead of vanishing
+    mut query: Query<(Entity, &mut PhysicsPosition, &Velocity, &Sprite), Without<DroppedItem>>,
     mut commands: Commands,
 ) {
-    let map_bound = Bound3D::from_world(&map.size());
+    let map_bound = Bound3D::from_world();
     'outer: for (entity, mut pos, vel, &sprite) in query.iter_mut() {
         let next_pos = pos.0 + vel.0;
-        if map_bound.out_of_bound(next_pos) {
+        if map_bound.out_of_bound(next_pos) || !map.is_loaded(ChunkPos::from_world(next_pos)) {
             commands.entity(entity).despawn();
             continue 'outer;
         }
         let aabb = sprite.into_aabb(next_pos);
-        for (_, blk) in map.scan_aabb(aabb) {
-            match blk.data {
-                crate::world::block::BlockType::Solid { .. } => {}
+        for (target, blk) in map.scan_aabb(aabb) {
+            if is_blocking(&registry, blk, target, pos.0, Axis::Y, vel.0.y) {
+                commands.entity(entity).despawn();
+                continue 'outer;
             }
-            commands.entity(entity).despawn();
-            continue 'outer;
         }
         pos.0 = next_pos;
     }
 }
 
+/// Entities touching a climbable block slide down slowly instead of falling
+fn climbing_system(
+    map: Res<Map>,
+    registry: Res<BlockRegistry>,
+    mut query: Query<(&PhysicsPosition, &mut Velocity, &ModelStructure)>,
+) {
+    for (pos, mut vel, structure) in query.iter_mut() {
+        let climbing = map
+            .scan_aabb(structure.into_aabb(pos.0))
+            .any(|(_, blk)| match blk {
+                Some(blk) => registry[blk].collision == Collision::Climbable,
+                None => false,
+            });
+        if climbing {
+            vel.0.y = vel.0.y.max(-0.05);
+        }
+    }
+}
+
 fn map_collision_detection(
     map: Res<Map>,
+    registry: Res<BlockRegistry>,
     mut query: Query<(
         &mut PhysicsPosition,
         &mut Velocity,
@@ -147,7 +262,7 @@ fn map_collision_detection(
         &ModelStructure,
     )>,
 ) {
-    let map_bound = Bound3D::from_world(&map.size());
+    let map_bound = Bound3D::from_world();
     for (mut pos, mut vel, mut cached_bound, &structure) in query.iter_mut() {
         let extent = structure.get_extent();
         *cached_bound = Default::default();
@@ -159,8 +274,8 @@ fn map_collision_detection(
                 .expanded(glam::vec3a(0.01, 0.0, 0.01));
             let mut voxel_bound = VoxelBound::default();
             for (target, blk) in map.scan_aabb(aabb) {
-                match blk.data {
-                    crate::world::block::BlockType::Solid { .. } => {}
+                if !is_blocking(&registry, blk, target, pos.0, axis, vel_axis) {
+                    continue;
                 }
                 let overlapped = AABB::from_block_pos(target) ^ aabb;
                 let tmp = VoxelBound::from_aabb_axis(overlapped, axis);
@@ -180,6 +295,198 @@ fn map_collision_detection(
     }
 }
 
+/// Physics ticks between two fluid steps
+const FLUID_DELAY: u32 = 6;
+
+/// Ticks until a block reacts to a change next to it, `None` for blocks
+/// that don't react
+fn tick_delay(registry: &BlockRegistry, block: Option<BlockId>) -> Option<u32> {
+    let block = &registry[block?];
+    if block.data.is_fluid() {
+        Some(FLUID_DELAY)
+    } else if block.flags.falls {
+        Some(1)
+    } else {
+        None
+    }
+}
+
+/// Publish block edits as events, relight them and schedule a tick for
+/// reacting blocks around them
+fn block_update_system(
+    registry: Res<BlockRegistry>,
+    mut map: ResMut<Map>,
+    mut ticks: ResMut<BlockTicks>,
+    mut events: EventWriter<BlockChanged>,
+) {
+    for change in map.take_changes() {
+        map.update_light(change.pos, &registry);
+        for pos in std::iter::once(change.pos).chain(block_neighbors(change.pos)) {
+            if let Some(delay) = map
+                .block_at(pos)
+                .and_then(|block| tick_delay(&registry, block))
+            {
+                ticks.schedule(pos, delay);
+            }
+        }
+        events.send(change);
+    }
+}
+
+/// Nothing to rest on, fluid doesn't hold blocks up either
+fn is_unsupported(map: &Map, registry: &BlockRegistry, pos: glam::IVec3) -> bool {
+    match map.block_at(pos - glam::IVec3::Y) {
+        Some(Some(blk)) => registry[blk].data.is_fluid(),
+        Some(None) => true,
+        // the bottom of the world and unloaded chunks hold everything
+        None => false,
+    }
+}
+
+const FALLING_STRUCTURE: ModelStructure = ModelStructure {
+    width: 0.98,
+    height: 0.98,
+    head_offset: 0.0,
+};
+
+/// Let blocks due for a tick react, fluids flow and unsupported blocks
+/// start to fall
+fn block_tick_system(
+    registry: Res<BlockRegistry>,
+    mut map: ResMut<Map>,
+    mut ticks: ResMut<BlockTicks>,
+    mut commands: Commands,
+) {
+    let mut fluids = Vec::new();
+    for pos in ticks.advance() {
+        let block = match map.block_at(pos) {
+            Some(Some(block)) => block,
+            _ => continue,
+        };
+        if registry[block].data.is_fluid() {
+            fluids.push(pos);
+        } else if registry[block].flags.falls && is_unsupported(&map, &registry, pos) {
+            map.set_block(pos, None);
+            let position = pos.as_f32() + glam::vec3(0.5, 0.0, 0.5);
+            commands.spawn_bundle((
+                FallingBlock(block),
+                Position(position.into()),
+                PhysicsPosition(position.into()),
+                Bound3D::from_world(),
+                Velocity::default(),
+                ReceiveGravity,
+                FALLING_STRUCTURE,
+            ));
+        }
+    }
+    if fluids.is_empty() {
+        return;
+    }
+    for pos in map.step_fluids(&registry, &fluids) {
+        // level changes don't show up as block changes
+        if map.fluid_at(&registry, pos).is_some() {
+            ticks.schedule(pos, FLUID_DELAY);
+        }
+    }
+}
+
+/// Let random blocks grow, spread or decay
+fn random_tick_system(
+    registry: Res<BlockRegistry>,
+    mut map: ResMut<Map>,
+    mut random: ResMut<RandomTicks>,
+) {
+    let RandomTicks { per_chunk, rng } = &mut *random;
+    map.random_ticks(&registry, *per_chunk, rng);
+}
+
+const ITEM_STRUCTURE: ModelStructure = ModelStructure {
+    width: 0.3,
+    height: 0.3,
+    head_offset: 0.0,
+};
+
+/// Cells above the impact point a landing block may take
+const LANDING_CLIMB: i32 = 1;
+
+/// Where a falling block ends up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Landing {
+    /// Put back into the map
+    Cell(glam::IVec3),
+    /// No free cell close to the impact point
+    Drop,
+}
+
+/// First cell from `target` up a landing block can take, `None` while a
+/// chunk on the way isn't loaded
+///
+/// Air, fluids and blocks without collision (like tall grass) are replaced,
+/// blocks that collide push the landing spot up by at most
+/// [`LANDING_CLIMB`] cells.
+fn landing_cell(map: &Map, registry: &BlockRegistry, target: glam::IVec3) -> Option<Landing> {
+    let collides = |blk: BlockId| {
+        !registry[blk].data.is_fluid()
+            && matches!(
+                registry[blk].collision,
+                Collision::Solid | Collision::Platform
+            )
+    };
+    for climb in 0..=LANDING_CLIMB {
+        let cell = target + glam::IVec3::Y * climb;
+        match map.block_at(cell)? {
+            Some(blk) if collides(blk) => continue,
+            _ => return Some(Landing::Cell(cell)),
+        }
+    }
+    Some(Landing::Drop)
+}
+
+/// Put falling blocks that stopped back into the map, or drop them as items
+/// when there's no room
+fn block_landing_system(
+    registry: Res<BlockRegistry>,
+    mut map: ResMut<Map>,
+    query: Query<(Entity, &FallingBlock, &PhysicsPosition, &Velocity)>,
+    mut commands: Commands,
+) {
+    for (entity, &FallingBlock(block), pos, vel) in query.iter() {
+        if vel.0.y != 0.0 {
+            continue;
+        }
+        let target = glam::IVec3::new(
+            pos.0.x.floor() as i32,
+            (pos.0.y + 0.5).floor() as i32,
+            pos.0.z.floor() as i32,
+        );
+        // wait for the chunk instead of losing the block
+        match landing_cell(&map, &registry, target) {
+            Some(Landing::Cell(cell)) => {
+                if cell != target {
+                    log::debug!("falling block at {} lands at {}", target, cell);
+                }
+                map.set_block(cell, Some(block));
+            }
+            Some(Landing::Drop) => {
+                log::debug!("falling block at {} dropped as an item", target);
+                let color = registry[block].data.color();
+                commands.spawn_bundle((
+                    DroppedItem(block),
+                    Sprite::new(color, ITEM_STRUCTURE.width / 2.0),
+                    Position(pos.0),
+                    PhysicsPosition(pos.0),
+                    Bound3D::from_world(),
+                    Velocity::default(),
+                    ReceiveGravity,
+                    ITEM_STRUCTURE,
+                ));
+            }
+            None => continue,
+        }
+        commands.entity(entity).despawn();
+    }
+}
+
 static PHYSICS_SIMULATION: &str = "physics simulation";
 
 pub struct PhysicsPlugin;
@@ -188,15 +495,40 @@ pub struct PhysicsPlugin;
 pub enum PhysicsLabel {
     SyncPosition,
     Gravity,
+    Climbing,
+    BlockTick,
+    RandomTick,
+    BlockUpdate,
     Collision,
+    FallingBlock,
     PlayerVelocity,
 }
 
 impl Plugin for PhysicsPlugin {
     fn build(&self, appb: &mut bevy_app::AppBuilder) {
         let mut stage = SystemStage::parallel().with_run_criteria(physics_tick_system.system());
+        // block edits first, then movement against the edited map, then
+        // landing blocks, so systems touching the map always run in one order
         stage
-            .add_system(gravity_system.system().label(PhysicsLabel::Gravity))
+            .add_system(block_tick_system.system().label(PhysicsLabel::BlockTick))
+            .add_system(
+                gravity_system
+                    .system()
+                    .label(PhysicsLabel::Gravity)
+                    .after(PhysicsLabel::RandomTick),
+            )
+            .add_system(
+                random_tick_system
+                    .system()
+                    .label(PhysicsLabel::RandomTick)
+                    .after(PhysicsLabel::BlockTick),
+            )
+            .add_system(
+                climbing_system
+                    .system()
+                    .label(PhysicsLabel::Climbing)
+                    .after(PhysicsLabel::Gravity),
+            )
             .add_system(
                 sprite_collision_system
                     .system()
@@ -207,7 +539,15 @@ impl Plugin for PhysicsPlugin {
                 map_collision_detection
                     .system()
                     .label(PhysicsLabel::Collision)
-                    .after(PhysicsLabel::Gravity),
+                    .after(PhysicsLabel::Gravity)
+                    .after(PhysicsLabel::Climbing)
+                    .after(PhysicsLabel::BlockTick),
+            )
+            .add_system(
+                block_landing_system
+                    .system()
+                    .label(PhysicsLabel::FallingBlock)
+                    .after(PhysicsLabel::Collision),
             )
             .add_system(
                 player_velocity_system
@@ -218,7 +558,16 @@ impl Plugin for PhysicsPlugin {
                     .after(PhysicsLabel::SyncPosition),
             );
         appb.insert_resource(PhysicsTimer(Timer::from_seconds(0.025, true)))
+            .init_resource::<BlockTicks>()
+            .init_resource::<RandomTicks>()
+            .add_event::<BlockChanged>()
             .add_stage_after(CoreStage::Update, PHYSICS_SIMULATION, stage)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                block_update_system
+                    .system()
+                    .label(PhysicsLabel::BlockUpdate),
+            )
             .add_system(
                 sync_position_system
                     .system()
@@ -226,3 +575,44 @@ impl Plugin for PhysicsPlugin {
             );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{
+        block::test_registry,
+        generator::flat::{FlatGenerator, Span},
+    };
+
+    #[test]
+    fn test_landing_cell() {
+        let registry = test_registry();
+        let block = |name| registry.lookup(name).ok();
+        let mut map = Map::new(FlatGenerator::new(&[Span(block("stone"), 4)]));
+        map.load_chunk(ChunkPos(0, 0, 0));
+        let target = glam::ivec3(3, 4, 3);
+        let cell = |pos| Some(Landing::Cell(pos));
+        assert_eq!(landing_cell(&map, &registry, target), cell(target));
+
+        map.set_block(target, block("tall_grass"));
+        assert_eq!(landing_cell(&map, &registry, target), cell(target));
+        map.set_block(target, block("water"));
+        assert_eq!(landing_cell(&map, &registry, target), cell(target));
+
+        // placed while the block was falling, land on top of it
+        map.set_block(target, block("stone"));
+        assert_eq!(
+            landing_cell(&map, &registry, target),
+            cell(target + glam::IVec3::Y)
+        );
+
+        // no room close by, the block is dropped
+        map.set_block(target + glam::IVec3::Y, block("sand"));
+        assert_eq!(landing_cell(&map, &registry, target), Some(Landing::Drop));
+
+        // the chunk above isn't loaded yet
+        let top = glam::ivec3(3, 15, 3);
+        map.set_block(top, block("stone"));
+        assert_eq!(landing_cell(&map, &registry, top), None);
+    }
+}
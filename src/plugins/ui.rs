@@ -1,7 +1,12 @@
 use bevy_app::{AppBuilder, Plugin};
 use bevy_ecs::prelude::*;
 
-use crate::renderer::pass::ui::{Texture, UiConcept};
+use crate::{
+    components::{Position, UserControl},
+    renderer::pass::ui::{Texture, UiConcept},
+    resources::RenderStats,
+    world::Map,
+};
 
 pub struct UiPlugin;
 
@@ -43,6 +48,26 @@ impl Plugin for UiPlugin {
                         {
                             ui.text(ImString::new(format!("entity count: {}", entity_count)));
                         }
+                        let stats = *world.get_resource::<RenderStats>().unwrap();
+                        ui.text(ImString::new(format!(
+                            "chunks: {}/{}",
+                            stats.drawn_chunks, stats.chunks
+                        )));
+                        let player = world
+                            .query_filtered::<&Position, With<UserControl>>()
+                            .iter(world)
+                            .last()
+                            .copied();
+                        if let Some(Position(pos)) = player {
+                            let map = world.get_resource::<Map>().unwrap();
+                            ui.text(ImString::new(format!(
+                                "position: {:.1} {:.1} {:.1}",
+                                pos.x, pos.y, pos.z
+                            )));
+                            if let Some(biome) = map.biome_at(pos) {
+                                ui.text(ImString::new(format!("biome: {}", biome.name)));
+                            }
+                        }
                     })
             }) as Box<dyn UiConcept>);
     }
@@ -7,9 +7,9 @@ use crate::{
     renderer::{camera::Camera, events::*, Action},
     resources::{ControlConfig, KeyboardTracing, PickedBlock},
     world::{
-        block::{Block, BlockType},
+        block::{BlockRegistry, BlockType},
         block_iter::BlockIter,
-        Map,
+        convert_pos, convert_pos_with_offset, Map,
     },
 };
 
@@ -27,6 +27,8 @@ fn init_user_input(mut commands: Commands) {
     tracing.add_key(A);
     tracing.add_key(S);
     tracing.add_key(D);
+    tracing.add_key(LShift);
+    tracing.add_key(LControl);
     commands.insert_resource(tracing);
 }
 
@@ -163,6 +165,9 @@ fn handle_paused_game(
 
 fn break_block_system(
     picked: Res<Option<PickedBlock>>,
+    control_config: Res<ControlConfig>,
+    registry: Res<BlockRegistry>,
+    keyboard_tracing: Res<KeyboardTracing>,
     mut map: ResMut<Map>,
     mut mouse_button_event_reader: EventReader<MouseButtonEvent>,
 ) {
@@ -177,22 +182,35 @@ fn break_block_system(
         }
         match event.button {
             MouseButton::Left => {
-                let (chunk_pos, block_sub_pos) = map.size().convert_pos(picked.position).unwrap();
+                let (chunk_pos, block_sub_pos) = match convert_pos(picked.position) {
+                    Some(pos) if map.is_loaded(pos.0) => pos,
+                    _ => continue,
+                };
+                if let Some(blk) = map[chunk_pos][block_sub_pos] {
+                    if registry[blk].flags.unbreakable {
+                        continue;
+                    }
+                }
                 log::info!(
                     "breaking {} ({} {})",
                     picked.position,
                     chunk_pos,
                     block_sub_pos
                 );
-                map[chunk_pos][block_sub_pos].take();
+                map.set_block(picked.position, None);
             }
             MouseButton::Right => {
-                if let Some((chunk_pos, block_sub_pos)) = map
-                    .size()
-                    .convert_pos_with_offset(picked.position, picked.direction.into())
-                {
-                    map[chunk_pos][block_sub_pos]
-                        .replace(crate::world::block::constants::YELLOW_BLOCK);
+                let offset: glam::IVec3 = picked.direction.into();
+                if convert_pos_with_offset(picked.position, offset).is_some() {
+                    let pressed = |key| keyboard_tracing[key] == ElementState::Pressed;
+                    let block = if pressed(VirtualKeyCode::LShift) {
+                        control_config.light_block
+                    } else if pressed(VirtualKeyCode::LControl) {
+                        control_config.fluid_block
+                    } else {
+                        control_config.place_block
+                    };
+                    map.set_block(picked.position + offset, Some(block));
                 }
             }
             _ => {}
@@ -200,22 +218,27 @@ fn break_block_system(
     }
 }
 
-fn picking_system(mut picked: ResMut<Option<PickedBlock>>, map: Res<Map>, camera: Res<Camera>) {
+fn picking_system(
+    mut picked: ResMut<Option<PickedBlock>>,
+    map: Res<Map>,
+    registry: Res<BlockRegistry>,
+    camera: Res<Camera>,
+) {
     let position = camera.eye;
     let direction = camera.get_direction();
-    let size = map.size();
 
-    *picked = BlockIter::new(size, position, direction)
+    *picked = BlockIter::new(position, direction)
         .map(|iter| {
             iter.take_while(|result| result.length <= 8.0)
                 .find_map(|result| {
                     let (chunk_pos, block_pos) = result.get_position();
-                    map[chunk_pos][block_pos].map(|blk| {
-                        match blk {
-                            Block {
-                                data: BlockType::Solid { .. },
-                                ..
-                            } => {}
+                    map.get(chunk_pos)?[block_pos].map(|blk| {
+                        match registry[blk].data {
+                            BlockType::Solid { .. }
+                            | BlockType::Emissive { .. }
+                            | BlockType::Transparent { .. }
+                            | BlockType::Translucent { .. }
+                            | BlockType::Fluid { .. } => {}
                         }
                         PickedBlock {
                             position: result.fine_position,
@@ -0,0 +1,100 @@
+use bevy_app::{CoreStage, EventWriter, Plugin};
+use bevy_ecs::prelude::*;
+
+use crate::{
+    components::{Position, UserControl},
+    world::{block::BlockRegistry, ChunkPos, Map},
+};
+
+/// Controls which chunks are kept in memory
+#[derive(Debug, Clone, Copy)]
+pub struct StreamingConfig {
+    /// Chunks within this distance (in chunks) of a player are loaded
+    pub view_radius: i32,
+    /// Keep unloaded chunks in memory so edits survive and get saved
+    pub save_unloaded: bool,
+}
+
+impl Default for StreamingConfig {
+    fn default() -> Self {
+        Self {
+            view_radius: 4,
+            save_unloaded: true,
+        }
+    }
+}
+
+fn chunk_streaming_system(
+    config: Res<StreamingConfig>,
+    mut map: ResMut<Map>,
+    query: Query<&Position, With<UserControl>>,
+) {
+    let centers: Vec<ChunkPos> = query
+        .iter()
+        .map(|pos| ChunkPos::from_world(pos.0))
+        .collect();
+    if centers.is_empty() {
+        return;
+    }
+    for &center in &centers {
+        for pos in center.iter_range(config.view_radius) {
+            map.request_chunk(pos);
+        }
+    }
+    // one extra chunk of slack so walking along a border doesn't thrash
+    let far: Vec<ChunkPos> = map
+        .loaded()
+        .filter(|pos| {
+            centers
+                .iter()
+                .all(|center| pos.distance(*center) > config.view_radius + 1)
+        })
+        .collect();
+    for pos in far {
+        map.unload_chunk(pos, config.save_unloaded);
+    }
+}
+
+/// Sent once a chunk has been generated (or restored) and lit, so it can be
+/// rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkReadyEvent(pub ChunkPos);
+
+fn chunk_generation_system(
+    registry: Res<BlockRegistry>,
+    mut map: ResMut<Map>,
+    mut ready: EventWriter<ChunkReadyEvent>,
+) {
+    for pos in map.poll_generation() {
+        map.light_chunk(pos, &registry);
+        ready.send(ChunkReadyEvent(pos));
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemLabel)]
+pub enum WorldStreamingLabel {
+    Streaming,
+    Generation,
+}
+
+pub struct WorldStreamingPlugin;
+
+impl Plugin for WorldStreamingPlugin {
+    fn build(&self, appb: &mut bevy_app::AppBuilder) {
+        appb.init_resource::<StreamingConfig>()
+            .add_event::<ChunkReadyEvent>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                chunk_streaming_system
+                    .system()
+                    .label(WorldStreamingLabel::Streaming),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                chunk_generation_system
+                    .system()
+                    .label(WorldStreamingLabel::Generation)
+                    .after(WorldStreamingLabel::Streaming),
+            );
+    }
+}
@@ -0,0 +1,243 @@
+use std::{collections::HashMap, fs::File, path::Path};
+
+use anyhow::{ensure, Context};
+use enum_map::EnumMap;
+use strum::IntoEnumIterator;
+
+use crate::{common::direction::Direction, world::block::BlockRegistry};
+
+/// Width and height of a tile in pixels
+pub const TILE_SIZE: usize = 16;
+
+/// Block textures packed into one square image
+///
+/// Tiles are named after their file, tile 0 is a checkerboard shown in place
+/// of tiles that are missing.
+pub struct TextureAtlas {
+    /// Tiles along each side of the image
+    columns: usize,
+    /// RGBA, rows from the top of the image
+    pixels: Vec<u8>,
+    tiles: HashMap<String, u32>,
+}
+
+impl TextureAtlas {
+    /// Pack tiles of `TILE_SIZE * TILE_SIZE` RGBA pixels
+    pub fn new(tiles: Vec<(String, Vec<u8>)>) -> anyhow::Result<Self> {
+        let count = tiles.len() + 1;
+        let columns = (1..).find(|columns| columns * columns >= count).unwrap();
+        let size = columns * TILE_SIZE;
+        let mut atlas = Self {
+            columns,
+            pixels: vec![0; size * size * 4],
+            tiles: HashMap::with_capacity(tiles.len()),
+        };
+        atlas.put(0, &checkerboard());
+        for (index, (name, pixels)) in tiles.into_iter().enumerate() {
+            ensure!(
+                pixels.len() == TILE_SIZE * TILE_SIZE * 4,
+                "tile {:?} is not {} * {} pixels",
+                name,
+                TILE_SIZE,
+                TILE_SIZE
+            );
+            let tile = index as u32 + 1;
+            atlas.put(tile, &pixels);
+            atlas.tiles.insert(name, tile);
+        }
+        Ok(atlas)
+    }
+
+    /// Every `.png` file in `dir`
+    pub fn load(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let dir = dir.as_ref();
+        let entries = std::fs::read_dir(dir)
+            .with_context(|| format!("failed to read textures {}", dir.display()))?;
+        let mut tiles = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("png") {
+                continue;
+            }
+            let name = match path.file_stem().and_then(|name| name.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let pixels = decode_png(&path)
+                .with_context(|| format!("failed to load texture {}", path.display()))?;
+            tiles.push((name, pixels));
+        }
+        // keep tile numbers stable between runs
+        tiles.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Self::new(tiles)
+    }
+
+    fn put(&mut self, tile: u32, pixels: &[u8]) {
+        let tile = tile as usize;
+        let (x, y) = (tile % self.columns, tile / self.columns);
+        let stride = self.size() * 4;
+        for row in 0..TILE_SIZE {
+            let start = (y * TILE_SIZE + row) * stride + x * TILE_SIZE * 4;
+            let source = &pixels[row * TILE_SIZE * 4..(row + 1) * TILE_SIZE * 4];
+            self.pixels[start..start + TILE_SIZE * 4].copy_from_slice(source);
+        }
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    /// Width and height of the image in pixels
+    pub fn size(&self) -> usize {
+        self.columns * TILE_SIZE
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Tile number, the checkerboard if there is no such tile
+    pub fn tile(&self, name: &str) -> u32 {
+        match self.tiles.get(name) {
+            Some(&tile) => tile,
+            None => 0,
+        }
+    }
+
+    /// Tile of every face of every block, `None` for blocks without texture
+    pub fn block_tiles(&self, registry: &BlockRegistry) -> Vec<EnumMap<Direction, Option<u32>>> {
+        registry
+            .iter()
+            .map(|(_, block)| {
+                let mut tiles = EnumMap::default();
+                if let Some(texture) = block.data.texture() {
+                    for direction in Direction::iter() {
+                        let name = texture.tile(direction);
+                        if !self.tiles.contains_key(name) {
+                            log::warn!("missing tile {:?} of {:?}", name, block.name);
+                        }
+                        tiles[direction] = Some(self.tile(name));
+                    }
+                }
+                tiles
+            })
+            .collect()
+    }
+}
+
+/// Magenta and black squares, hard to miss
+fn checkerboard() -> Vec<u8> {
+    let half = TILE_SIZE / 2;
+    itertools::iproduct!(0..TILE_SIZE, 0..TILE_SIZE)
+        .flat_map(|(y, x)| {
+            if (x / half + y / half) % 2 == 1 {
+                vec![0, 0, 0, 255]
+            } else {
+                vec![255, 0, 255, 255]
+            }
+        })
+        .collect()
+}
+
+/// Decode to 8 bit RGBA
+fn decode_png(path: &Path) -> anyhow::Result<Vec<u8>> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info()?;
+    let mut buffer = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buffer)?;
+    let pixels = match info.color_type {
+        png::ColorType::RGBA => buffer,
+        png::ColorType::RGB => buffer
+            .chunks(3)
+            .flat_map(|rgb| vec![rgb[0], rgb[1], rgb[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buffer
+            .chunks(2)
+            .flat_map(|ga| vec![ga[0], ga[0], ga[0], ga[1]])
+            .collect(),
+        png::ColorType::Grayscale => buffer.iter().flat_map(|&g| vec![g, g, g, 255]).collect(),
+        png::ColorType::Indexed => unreachable!("palette is expanded"),
+    };
+    ensure!(
+        (info.width, info.height) == (TILE_SIZE as u32, TILE_SIZE as u32),
+        "texture is {} * {} pixels instead of {} * {}",
+        info.width,
+        info.height,
+        TILE_SIZE,
+        TILE_SIZE
+    );
+    Ok(pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::block::test_registry;
+
+    fn solid(r: u8) -> Vec<u8> {
+        [r, 0, 0, 255].repeat(TILE_SIZE * TILE_SIZE)
+    }
+
+    fn pixel(atlas: &TextureAtlas, x: usize, y: usize) -> &[u8] {
+        let start = (y * atlas.size() + x) * 4;
+        &atlas.pixels()[start..start + 4]
+    }
+
+    #[test]
+    fn test_pack() {
+        let tiles = (1..=4).map(|r| (format!("tile{}", r), solid(r))).collect();
+        let atlas = TextureAtlas::new(tiles).unwrap();
+        // 5 tiles with the checkerboard
+        assert_eq!(atlas.columns(), 3);
+        assert_eq!(atlas.pixels().len(), atlas.size() * atlas.size() * 4);
+        assert_eq!(atlas.tile("tile1"), 1);
+        assert_eq!(atlas.tile("tile4"), 4);
+        assert_eq!(pixel(&atlas, TILE_SIZE, 0), &[1, 0, 0, 255]);
+        // tile 3 starts the second row
+        assert_eq!(pixel(&atlas, 0, TILE_SIZE), &[3, 0, 0, 255]);
+        let end = 2 * TILE_SIZE - 1;
+        assert_eq!(pixel(&atlas, end, end), &[4, 0, 0, 255]);
+
+        assert!(TextureAtlas::new(vec![("small".to_string(), vec![0; 4])]).is_err());
+    }
+
+    #[test]
+    fn test_fallback() {
+        let atlas = TextureAtlas::new(vec![("stone".to_string(), solid(1))]).unwrap();
+        assert_eq!(atlas.tile("missing"), 0);
+        assert_eq!(pixel(&atlas, 0, 0), &[255, 0, 255, 255]);
+        assert_eq!(pixel(&atlas, TILE_SIZE / 2, 0), &[0, 0, 0, 255]);
+
+        let registry = test_registry();
+        let tiles = atlas.block_tiles(&registry);
+        let stone = tiles[registry.lookup("stone").unwrap().index()];
+        assert!(Direction::iter().all(|direction| stone[direction] == Some(1)));
+        // textured, but none of its tiles are in the atlas
+        let grass = tiles[registry.lookup("grass").unwrap().index()];
+        assert_eq!(grass[Direction::Up], Some(0));
+        let yellow = tiles[registry.lookup("yellow").unwrap().index()];
+        assert_eq!(yellow[Direction::Up], None);
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = std::env::temp_dir().join(format!("atlas-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = File::create(dir.join("red.png")).unwrap();
+        let mut encoder = png::Encoder::new(file, TILE_SIZE as u32, TILE_SIZE as u32);
+        encoder.set_color(png::ColorType::RGB);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer
+            .write_image_data(&[200, 0, 0].repeat(TILE_SIZE * TILE_SIZE))
+            .unwrap();
+        drop(writer);
+        std::fs::write(dir.join("notes.txt"), "not a texture").unwrap();
+
+        let atlas = TextureAtlas::load(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(atlas.tile("red"), 1);
+        assert_eq!(pixel(&atlas, TILE_SIZE, 0), &[200, 0, 0, 255]);
+    }
+}
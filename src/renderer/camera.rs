@@ -1,5 +1,7 @@
 use std::ops::Range;
 
+use crate::math::frustum::Frustum;
+
 #[derive(Debug, Clone)]
 pub struct Camera {
     pub eye: glam::Vec3A,
@@ -30,6 +32,11 @@ impl Camera {
         glam::Mat4::perspective_rh_gl(*fov, aspect_ratio, hard_range.start, hard_range.end)
     }
 
+    /// Visible volume, the far plane sits at the end of `hard_range`
+    pub fn frustum(&self, aspect_ratio: f32) -> Frustum {
+        Frustum::from_matrix(self.perspective(aspect_ratio) * self.view_model())
+    }
+
     pub fn get_direction(&self) -> glam::Vec3A {
         let &Self { yaw, pitch, .. } = self;
         let rot_y = glam::Mat3::from_rotation_y(-yaw);
@@ -4,6 +4,9 @@ use bevy_ecs::prelude::*;
 use glium::glutin::{self, event::KeyboardInput};
 use pass::PassContext;
 
+use crate::resources::RenderStats;
+
+pub mod atlas;
 pub mod buffers;
 pub mod camera;
 pub mod events;
@@ -44,6 +47,7 @@ impl<P: Pass + 'static> Plugin for RenderPlugin<P> {
             .add_event::<MouseMotionEvent>()
             .add_event::<KeyboardInput>()
             .add_event::<Action>()
+            .init_resource::<RenderStats>()
             .add_system(convert_appexit_to_action.system())
             .set_runner(RenderPlugin::<P>::run);
     }
@@ -113,7 +117,7 @@ impl<P: Pass + 'static> RenderPlugin<P> {
                 }
                 Event::WindowEvent { event, .. } => match event {
                     WindowEvent::CloseRequested => {
-                        *control_flow = glutin::event_loop::ControlFlow::Exit;
+                        send_event(&mut app, Action::Exit);
                     }
                     WindowEvent::Focused(focused) => {
                         send_event(&mut app, FocusedEvent(focused));
@@ -1,21 +1,27 @@
-use itertools::Itertools;
-use rayon::prelude::*;
-use std::{collections::BTreeMap, time::Instant};
+use bevy_ecs::prelude::QueryState;
+use enum_map::EnumMap;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
 
 use super::{Pass, PassContext};
 use crate::{
     common::{
-        color::Color,
         direction::Direction,
         vertex_cache::{VertexCache, VertexWriter},
     },
+    components::{FallingBlock, Position},
     math::axis::MapAxisExt,
-    renderer::buffers::SurfaceProvider,
-    resources::PickedBlock,
+    renderer::{atlas::TextureAtlas, buffers::SurfaceProvider},
+    resources::{PickedBlock, RenderStats},
     shader_program,
     world::{
-        block::{Block, BlockType},
-        chunk::{BlockSubPos, Chunk},
+        block::{BlockId, BlockRegistry},
+        light::{Light, MAX_LIGHT},
+        lod::Lod,
+        mesh::{MeshSlice, Quad},
+        meshing::MeshQueue,
         ChunkPos, Map,
     },
 };
@@ -27,9 +33,24 @@ struct FaceInfo {
     position: (f32, f32, f32),
     color: (f32, f32, f32),
     face: u32,
+    /// Skylight and block light in front of the face, `0.0..=1.0`
+    light: (f32, f32),
+    /// Below 1.0 for translucent blocks
+    alpha: f32,
+    /// Below 1.0 for fluid that isn't a full block
+    height: f32,
+    /// Size of merged faces in blocks
+    extent: (f32, f32, f32),
+    /// Ambient occlusion of each corner, 0.0 is fully occluded
+    ao: (f32, f32, f32, f32),
+    /// Atlas tile, -1 for faces drawn with the block color
+    tile: i32,
 }
 
-implement_vertex!(FaceInfo, position, color, face);
+implement_vertex!(FaceInfo, position, color, face, light, alpha, height, extent, ao, tile);
+
+/// Atlas tile of each face of each block, see [`TextureAtlas::block_tiles`]
+type BlockTiles = Vec<EnumMap<Direction, Option<u32>>>;
 
 #[derive(Debug, Clone, Copy)]
 struct PickedUniformBlock {
@@ -46,143 +67,322 @@ impl From<PickedBlock> for PickedUniformBlock {
     }
 }
 
-pub struct CubePass {
+/// Faces of a chunk kept per [`MeshSlice`], so edits only remesh the
+/// slices they touch
+struct ChunkMesh {
+    /// Chunk revision when the mesh was made, older snapshots were taken
+    /// from a copy of the chunk that got unloaded
+    since: u64,
+    /// Level the chunk is meshed at, updates for other levels are dropped
+    lod: Lod,
+    /// Level of the slices, the old mesh is drawn until the first update
+    /// for a new level comes in
+    shown: Lod,
+    seams: EnumMap<Direction, bool>,
+    /// Faces of each slice with the snapshot revision and job they were
+    /// built by, the job orders snapshots of the same revision
+    slices: BTreeMap<MeshSlice, ((u64, u64), Vec<FaceInfo>)>,
+    opaque: Option<VertexCache<FaceInfo>>,
+    translucent: Vec<FaceInfo>,
+}
+
+impl ChunkMesh {
+    fn new(since: u64, lod: Lod) -> Self {
+        Self {
+            since,
+            lod,
+            shown: lod,
+            seams: EnumMap::default(),
+            slices: Default::default(),
+            opaque: None,
+            translucent: Vec::new(),
+        }
+    }
+}
+
+pub struct CubePass<'w> {
     program: glium::Program,
-    chunk_cache: BTreeMap<ChunkPos, VertexCache<FaceInfo>>,
+    meshes: BTreeMap<ChunkPos, ChunkMesh>,
+    /// Chunks in view range and inside the frustum this frame
+    visible: Vec<ChunkPos>,
+    /// Chunks are meshed on the rayon pool, the render thread only uploads
+    queue: MeshQueue<FaceInfo>,
+    /// Translucent faces of all chunks sorted back to front, uploaded every
+    /// frame
+    translucent: Option<VertexCache<FaceInfo>>,
+    /// Blocks falling as entities, drawn with the opaque faces
+    falling: Option<VertexCache<FaceInfo>>,
+    atlas: glium::texture::Texture2d,
+    atlas_columns: u32,
+    tiles: Arc<BlockTiles>,
+    qs: QueryState<(&'w FallingBlock, &'w Position)>,
+}
+
+/// Color and atlas tile of a block face, textured faces take their color
+/// from the tile
+fn surface(
+    registry: &BlockRegistry,
+    tiles: &BlockTiles,
+    block: BlockId,
+    direction: Direction,
+) -> ((f32, f32, f32), i32) {
+    match tiles[block.index()][direction] {
+        Some(tile) => ((1.0, 1.0, 1.0), tile as i32),
+        None => (registry[block].data.color().into(), -1),
+    }
 }
 
-fn test_block(chunk: &Chunk, block_pos: BlockSubPos) -> bool {
-    if let Some(Block {
-        data: BlockType::Solid { .. },
-        ..
-    }) = chunk[block_pos]
-    {
-        true
-    } else {
-        false
+/// Vertex for a quad from the mesher
+fn face_info(registry: &BlockRegistry, tiles: &BlockTiles, quad: &Quad) -> FaceInfo {
+    let data = &registry[quad.block].data;
+    let level = |level: u8| level as f32 / MAX_LIGHT as f32;
+    let ao = |corner: usize| quad.ao[corner] as f32 / 3.0;
+    let (color, tile) = surface(registry, tiles, quad.block, quad.direction);
+    FaceInfo {
+        position: quad.position.as_f32().into(),
+        color,
+        face: quad.direction as u32,
+        light: (level(quad.light.sky()), level(quad.light.block())),
+        alpha: data.opacity(),
+        height: quad.height,
+        extent: quad.extent.as_f32().into(),
+        ao: (ao(0), ao(1), ao(2), ao(3)),
+        tile,
     }
 }
 
-fn gen_face(
+/// All faces of a falling block, lit like the block it is in
+fn falling_faces<'a>(
+    registry: &'a BlockRegistry,
+    tiles: &'a BlockTiles,
     map: &Map,
-    chunk_pos: ChunkPos,
-    chunk: &Chunk,
-    color: Color,
-    block_pos: BlockSubPos,
-    direction: Direction,
-) -> Option<FaceInfo> {
-    match block_pos + direction {
-        Some(neighbor) => {
-            if test_block(chunk, neighbor) {
-                return None;
-            }
-        }
-        None => {
-            use crate::world::ChunkNeighbor;
-            let (x, y, z) = block_pos.into();
-            match direction {
-                Direction::West => {
-                    if let Some(neighbor_chunk_pos) =
-                        chunk_pos.get_neighbor(map.size(), ChunkNeighbor::West)
-                    {
-                        let neighbor_chunk = &map[neighbor_chunk_pos];
-                        if test_block(
-                            neighbor_chunk,
-                            BlockSubPos::new((Chunk::WIDTH - 1) as u8, y, z),
-                        ) {
-                            return None;
-                        }
-                    }
-                }
-                Direction::East => {
-                    if let Some(neighbor_chunk_pos) =
-                        chunk_pos.get_neighbor(map.size(), ChunkNeighbor::East)
-                    {
-                        let neighbor_chunk = &map[neighbor_chunk_pos];
-                        if test_block(neighbor_chunk, BlockSubPos::new(0, y, z)) {
-                            return None;
-                        }
-                    }
-                }
-                Direction::North => {
-                    if let Some(neighbor_chunk_pos) =
-                        chunk_pos.get_neighbor(map.size(), ChunkNeighbor::North)
-                    {
-                        let neighbor_chunk = &map[neighbor_chunk_pos];
-                        if test_block(
-                            neighbor_chunk,
-                            BlockSubPos::new(x, y, (Chunk::WIDTH - 1) as u8),
-                        ) {
-                            return None;
-                        }
-                    }
-                }
-                Direction::South => {
-                    if let Some(neighbor_chunk_pos) =
-                        chunk_pos.get_neighbor(map.size(), ChunkNeighbor::South)
-                    {
-                        let neighbor_chunk = &map[neighbor_chunk_pos];
-                        if test_block(neighbor_chunk, BlockSubPos::new(x, y, 0)) {
-                            return None;
-                        }
-                    }
-                }
-                _ => {}
-            }
+    block: BlockId,
+    position: glam::Vec3A,
+) -> impl 'a + Iterator<Item = FaceInfo> {
+    let cell = glam::IVec3::new(
+        position.x.floor() as i32,
+        (position.y + 0.5).floor() as i32,
+        position.z.floor() as i32,
+    );
+    let light = match map.light_at(cell) {
+        Some(light) => light,
+        None => Light::SKY,
+    };
+    let level = |level: u8| level as f32 / MAX_LIGHT as f32;
+    let data = &registry[block].data;
+    let corner = position - glam::vec3a(0.5, 0.0, 0.5);
+    let face = FaceInfo {
+        position: corner.into(),
+        color: data.color().into(),
+        face: 0,
+        light: (level(light.sky()), level(light.block())),
+        alpha: data.opacity(),
+        height: 1.0,
+        extent: (1.0, 1.0, 1.0),
+        ao: (1.0, 1.0, 1.0, 1.0),
+        tile: -1,
+    };
+    Direction::iter().map(move |direction| {
+        let (color, tile) = surface(registry, tiles, block, direction);
+        FaceInfo {
+            face: direction as u32,
+            color,
+            tile,
+            ..face
         }
-    }
-    Some(FaceInfo {
-        position: (chunk_pos + block_pos).into(),
-        color: color.into(),
-        face: direction as u32,
     })
 }
 
-impl Pass for CubePass {
-    fn new(_context: &mut PassContext<'_>, display: &glium::Display) -> anyhow::Result<Self> {
+/// Replace the cache content, growing it when the faces don't fit
+fn upload(
+    cache: &mut Option<VertexCache<FaceInfo>>,
+    display: &glium::Display,
+    faces: Vec<FaceInfo>,
+) {
+    let capacity = match cache {
+        Some(cache) => cache.capacity(),
+        None => 0,
+    };
+    if capacity < faces.len().max(1) {
+        let capacity = faces.len().next_power_of_two().max(1024);
+        *cache = Some(VertexCache::new(display, capacity));
+    }
+    let mut writer = VertexWriter::new(cache.as_mut().unwrap());
+    for face in faces {
+        writer.write(face);
+    }
+}
+
+impl<'w> Pass for CubePass<'w> {
+    fn new(context: &mut PassContext<'_>, display: &glium::Display) -> anyhow::Result<Self> {
+        let registry = context.get_res::<BlockRegistry>().clone();
+        let atlas = context.get_res::<TextureAtlas>();
+        let tiles = Arc::new(atlas.block_tiles(&registry));
+        let size = atlas.size() as u32;
+        let image =
+            glium::texture::RawImage2d::from_raw_rgba_reversed(atlas.pixels(), (size, size));
+        let texture = glium::texture::Texture2d::with_mipmaps(
+            display,
+            image,
+            glium::texture::MipmapsOption::NoMipmap,
+        )?;
+        let vertex_tiles = tiles.clone();
         Ok(Self {
             program: shader_program!(display, "cube" with geometry)?,
-            chunk_cache: Default::default(),
+            meshes: Default::default(),
+            visible: Vec::new(),
+            queue: MeshQueue::new(Arc::new(registry), move |registry, quad| {
+                face_info(registry, &vertex_tiles, quad)
+            }),
+            translucent: None,
+            falling: None,
+            atlas: texture,
+            atlas_columns: atlas.columns() as u32,
+            tiles,
+            qs: context.world.query::<(&FallingBlock, &Position)>(),
         })
     }
 
     fn prepare(&mut self, context: &mut PassContext, display: &glium::Display) {
         let map = context.map();
-        map.iter()
-            .filter_map(|(chunk_pos, chunk)| {
-                let cached = self.chunk_cache.entry(chunk_pos);
-                let mut dirty = chunk.dirty()
-                    || map
-                        .iter_neighbor(chunk_pos)
-                        .any(|(_, neighbor)| neighbor.dirty());
-                let cache = cached.or_insert_with(|| {
-                    dirty = true;
-                    VertexCache::new(display, Chunk::max_face_count())
-                });
-                if dirty {
-                    let now = Instant::now();
-                    let data: Vec<_> = chunk
-                        .par_iter_solid()
-                        .flat_map_iter(|(block_pos, color)| {
-                            Direction::iter().map(move |direction| (block_pos, color, direction))
-                        })
-                        .filter_map(|(block_pos, color, direction)| {
-                            gen_face(&*map, chunk_pos, chunk, color, block_pos, direction)
-                        })
-                        .collect();
-                    let mut writer = VertexWriter::new(cache);
-                    for face in data {
-                        writer.write(face);
+        let registry = context.get_res::<BlockRegistry>();
+        let camera = context.camera();
+        // only ready chunks are meshed, drop meshes of chunks that went away
+        self.meshes.retain(|&pos, _| map.is_loaded(pos));
+        let lods: BTreeMap<ChunkPos, Lod> = map
+            .iter()
+            .map(|(pos, _)| {
+                let distance = pos.aabb().distance_squared(camera.eye).sqrt();
+                (pos, Lod::for_distance(distance))
+            })
+            .collect();
+        for (&chunk_pos, &lod) in &lods {
+            let mut seams = EnumMap::default();
+            for direction in Direction::iter() {
+                seams[direction] = match lods.get(&chunk_pos.get_neighbor(direction.into())) {
+                    Some(&neighbor) => neighbor != lod,
+                    None => false,
+                };
+            }
+            let is_new = !self.meshes.contains_key(&chunk_pos);
+            let revision = map[chunk_pos].revision();
+            let mesh = self
+                .meshes
+                .entry(chunk_pos)
+                .or_insert_with(|| ChunkMesh::new(revision, lod));
+            let slices: BTreeSet<MeshSlice> = if is_new || mesh.lod != lod {
+                MeshSlice::all(lod).collect()
+            } else {
+                let mut slices = match lod {
+                    Lod::Full => map.dirty_slices(chunk_pos),
+                    // cells reach deeper into the neighbors than dirty slices
+                    _ => {
+                        let dirty = |pos| match map.get(pos) {
+                            Some(chunk) => chunk.dirty(),
+                            None => false,
+                        };
+                        if dirty(chunk_pos) || chunk_pos.neighbors().any(dirty) {
+                            MeshSlice::all(lod).collect()
+                        } else {
+                            BTreeSet::new()
+                        }
                     }
-                    log::info!("render chunk@{:?} took {:?}", chunk_pos, now.elapsed());
-                    Some(chunk)
-                } else {
-                    None
+                };
+                for direction in Direction::iter() {
+                    if seams[direction] != mesh.seams[direction] {
+                        slices.insert(MeshSlice::boundary(direction, lod));
+                    }
+                }
+                slices
+            };
+            mesh.lod = lod;
+            mesh.seams = seams;
+            if !slices.is_empty() {
+                let snapshot = map.lod_snapshot(chunk_pos, lod).unwrap();
+                self.queue
+                    .submit(snapshot.with_seams(seams), slices.into_iter().collect());
+            }
+        }
+        // neighbors look at each other's changes, so clean up afterwards
+        for (_, chunk) in map.iter() {
+            chunk.mark_clean();
+        }
+
+        let mut updated = BTreeSet::new();
+        for update in self.queue.poll() {
+            let mesh = match self.meshes.get_mut(&update.pos) {
+                // the chunk was loaded again or changed level after the job
+                // started
+                Some(mesh) if update.revision >= mesh.since && update.lod == mesh.lod => mesh,
+                _ => continue,
+            };
+            if mesh.shown != update.lod {
+                mesh.slices.clear();
+                mesh.shown = update.lod;
+            }
+            let built = (update.revision, update.job);
+            for (slice, faces) in update.slices {
+                let newer = match mesh.slices.get(&slice) {
+                    Some(&(other, _)) => built > other,
+                    None => true,
+                };
+                if newer {
+                    mesh.slices.insert(slice, (built, faces));
+                    updated.insert(update.pos);
                 }
+            }
+        }
+        for pos in updated {
+            let mesh = self.meshes.get_mut(&pos).unwrap();
+            let (translucent, opaque): (Vec<_>, Vec<_>) = mesh
+                .slices
+                .values()
+                .flat_map(|(_, faces)| faces)
+                .partition(|face| face.alpha < 1.0);
+            upload(&mut mesh.opaque, display, opaque);
+            mesh.translucent = translucent;
+        }
+
+        let frustum = camera.frustum(context.aspect_ratio);
+        let range = camera.hard_range.end;
+        self.visible = self
+            .meshes
+            .keys()
+            .copied()
+            .filter(|pos| {
+                let aabb = pos.aabb();
+                aabb.distance_squared(camera.eye) <= range * range && frustum.intersects(aabb)
             })
-            .collect_vec()
+            .collect();
+
+        // blending needs the farthest faces first
+        let eye = camera.eye;
+        let distance = |face: &FaceInfo| {
+            let (x, y, z) = face.position;
+            (glam::vec3a(x, y, z) + glam::Vec3A::splat(0.5)).distance_squared(eye)
+        };
+        let meshes = &self.meshes;
+        let mut faces: Vec<FaceInfo> = self
+            .visible
             .iter()
-            .for_each(|x| (**x).mark_clean());
+            .flat_map(|pos| &meshes[pos].translucent)
+            .copied()
+            .collect();
+        faces.sort_by(|a, b| distance(b).partial_cmp(&distance(a)).unwrap());
+        upload(&mut self.translucent, display, faces);
+
+        let mut faces = Vec::new();
+        let tiles = &self.tiles;
+        self.qs
+            .for_each(context.world, |(&FallingBlock(block), position)| {
+                faces.extend(falling_faces(registry, tiles, map, block, position.0));
+            });
+        upload(&mut self.falling, display, faces);
+
+        *context.world.get_resource_mut::<RenderStats>().unwrap() = RenderStats {
+            chunks: self.meshes.len(),
+            drawn_chunks: self.visible.len(),
+        };
     }
 
     fn process(
@@ -203,10 +403,19 @@ impl Pass for CubePass {
         };
         let picked_block = glium::uniforms::UniformBuffer::new(display, picked_block)?;
 
+        // tiles are a few pixels, so keep them sharp
+        let atlas = self
+            .atlas
+            .sampled()
+            .minify_filter(glium::uniforms::MinifySamplerFilter::Nearest)
+            .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest);
         let uniforms = uniform! {
             view_model: context.view_model,
             perspective: context.perspective,
             picked: &picked_block,
+            translucent: false,
+            atlas: atlas,
+            atlas_columns: self.atlas_columns,
         };
 
         let draw_parameters = glium::DrawParameters {
@@ -218,7 +427,11 @@ impl Pass for CubePass {
             backface_culling: glium::BackfaceCullingMode::CullClockwise,
             ..Default::default()
         };
-        for (_, cache) in &self.chunk_cache {
+        let opaque = self
+            .visible
+            .iter()
+            .filter_map(|pos| self.meshes[pos].opaque.as_ref());
+        for cache in opaque.chain(&self.falling) {
             frame.draw(
                 cache.as_slice(),
                 &glium::index::NoIndices(glium::index::PrimitiveType::Points),
@@ -227,6 +440,45 @@ impl Pass for CubePass {
                 &draw_parameters,
             )?;
         }
+
+        if let Some(cache) = self.translucent.as_ref().filter(|cache| cache.count > 0) {
+            let uniforms = uniform! {
+                view_model: context.view_model,
+                perspective: context.perspective,
+                picked: &picked_block,
+                translucent: true,
+                atlas: atlas,
+                atlas_columns: self.atlas_columns,
+            };
+            let draw_parameters = glium::DrawParameters {
+                depth: glium::Depth {
+                    test: glium::DepthTest::IfLess,
+                    write: false,
+                    ..Default::default()
+                },
+                // alpha of the color target holds the picked flag, keep it
+                blend: glium::Blend {
+                    color: glium::BlendingFunction::Addition {
+                        source: glium::LinearBlendingFactor::SourceAlpha,
+                        destination: glium::LinearBlendingFactor::OneMinusSourceAlpha,
+                    },
+                    alpha: glium::BlendingFunction::Addition {
+                        source: glium::LinearBlendingFactor::Zero,
+                        destination: glium::LinearBlendingFactor::One,
+                    },
+                    constant_value: (0.0, 0.0, 0.0, 0.0),
+                },
+                backface_culling: glium::BackfaceCullingMode::CullClockwise,
+                ..Default::default()
+            };
+            frame.draw(
+                cache.as_slice(),
+                glium::index::NoIndices(glium::index::PrimitiveType::Points),
+                &self.program,
+                &uniforms,
+                &draw_parameters,
+            )?;
+        }
         Ok(())
     }
 }
@@ -1,11 +1,19 @@
 mod keyboard_tracing;
 mod picked_block;
+mod render_stats;
 
 pub use keyboard_tracing::*;
 pub use picked_block::*;
+pub use render_stats::*;
+
+use crate::world::block::BlockId;
 
 #[derive(Debug, Clone, Copy)]
 pub struct ControlConfig {
     pub rotation_scale: glam::Vec2,
+    pub place_block: BlockId,
+    /// Placed instead of `place_block` while shift is held
+    pub light_block: BlockId,
+    /// Placed instead of `place_block` while control is held
+    pub fluid_block: BlockId,
 }
-
@@ -2,6 +2,6 @@ use crate::{common::direction::Direction};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct PickedBlock {
-    pub position: glam::UVec3,
+    pub position: glam::IVec3,
     pub direction: Direction,
 }
@@ -0,0 +1,8 @@
+/// Numbers from the last rendered frame
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RenderStats {
+    /// Chunks with a mesh
+    pub chunks: usize,
+    /// Chunks that passed distance and frustum culling
+    pub drawn_chunks: usize,
+}
@@ -0,0 +1,230 @@
+use noise::{NoiseFn, Seedable};
+use serde::Deserialize;
+
+use super::block::{BlockId, BlockRegistry};
+
+/// Climate weights fall off with `exp(-distance² / BLEND)`, smaller values
+/// give sharper borders between biomes
+const BLEND: f64 = 0.05;
+
+/// Column of blocks placed on top of the surface
+#[derive(Debug, Clone, Deserialize)]
+pub struct DecorationConfig {
+    pub block: String,
+    /// Probability per surface column
+    pub chance: f32,
+    pub height: u8,
+}
+
+/// Biome definition as written in the world config
+#[derive(Debug, Clone, Deserialize)]
+pub struct BiomeConfig {
+    pub name: String,
+    /// Preferred climate, both in `0.0..=1.0`
+    pub temperature: f64,
+    pub humidity: f64,
+    pub surface: String,
+    pub subsurface: String,
+    /// Number of subsurface blocks below the surface block
+    #[serde(default = "default_subsurface_depth")]
+    pub subsurface_depth: u8,
+    /// Multiplier of the terrain amplitude
+    #[serde(default = "default_height_scale")]
+    pub height_scale: f32,
+    #[serde(default)]
+    pub decoration: Option<DecorationConfig>,
+}
+
+fn default_subsurface_depth() -> u8 {
+    3
+}
+
+fn default_height_scale() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Decoration {
+    pub block: BlockId,
+    pub chance: f32,
+    pub height: u8,
+}
+
+/// Biome with resolved blocks
+#[derive(Debug, Clone)]
+pub struct Biome {
+    pub name: String,
+    pub temperature: f64,
+    pub humidity: f64,
+    pub surface: BlockId,
+    pub subsurface: BlockId,
+    pub subsurface_depth: u8,
+    pub height_scale: f32,
+    pub decoration: Option<Decoration>,
+}
+
+impl Biome {
+    pub fn from_config(config: &BiomeConfig, registry: &BlockRegistry) -> anyhow::Result<Self> {
+        let decoration = match &config.decoration {
+            Some(decoration) => Some(Decoration {
+                block: registry.lookup(&decoration.block)?,
+                chance: decoration.chance,
+                height: decoration.height,
+            }),
+            None => None,
+        };
+        Ok(Self {
+            name: config.name.clone(),
+            temperature: config.temperature,
+            humidity: config.humidity,
+            surface: registry.lookup(&config.surface)?,
+            subsurface: registry.lookup(&config.subsurface)?,
+            subsurface_depth: config.subsurface_depth,
+            height_scale: config.height_scale,
+            decoration,
+        })
+    }
+}
+
+/// Biome lookup result for a single column
+#[derive(Debug, Clone, Copy)]
+pub struct BiomeSample<'a> {
+    /// Dominant biome, decides blocks and decoration
+    pub biome: &'a Biome,
+    /// Height scale blended over all biomes by climate distance
+    pub height_scale: f32,
+}
+
+/// Temperature/humidity noise mapped to biome definitions
+pub struct BiomeMap {
+    seed: u32,
+    scale: f64,
+    temperature: noise::SuperSimplex,
+    humidity: noise::SuperSimplex,
+    biomes: Vec<Biome>,
+}
+
+impl BiomeMap {
+    pub fn new(seed: u32, scale: f64, biomes: Vec<Biome>) -> Self {
+        assert!(!biomes.is_empty(), "empty biome list");
+        Self {
+            seed,
+            scale,
+            temperature: noise::SuperSimplex::new().set_seed(seed.wrapping_add(2)),
+            humidity: noise::SuperSimplex::new().set_seed(seed.wrapping_add(3)),
+            biomes,
+        }
+    }
+
+    pub fn biomes(&self) -> &[Biome] {
+        &self.biomes
+    }
+
+    /// Temperature and humidity at the column, both in `0.0..=1.0`
+    pub fn climate(&self, x: i32, z: i32) -> (f64, f64) {
+        let pos = [x as f64 * self.scale, z as f64 * self.scale];
+        let map = |value: f64| (value * 0.5 + 0.5).clamp(0.0, 1.0);
+        (map(self.temperature.get(pos)), map(self.humidity.get(pos)))
+    }
+
+    pub fn sample(&self, x: i32, z: i32) -> BiomeSample<'_> {
+        let (temperature, humidity) = self.climate(x, z);
+        let mut best = (f64::INFINITY, &self.biomes[0]);
+        let (mut total, mut height_scale) = (0.0, 0.0);
+        for biome in &self.biomes {
+            let dt = biome.temperature - temperature;
+            let dh = biome.humidity - humidity;
+            let distance = dt * dt + dh * dh;
+            if distance < best.0 {
+                best = (distance, biome);
+            }
+            let weight = (-distance / BLEND).exp();
+            total += weight;
+            height_scale += weight * biome.height_scale as f64;
+        }
+        BiomeSample {
+            biome: best.1,
+            height_scale: if total > 0.0 {
+                (height_scale / total) as f32
+            } else {
+                best.1.height_scale
+            },
+        }
+    }
+
+    pub fn biome_at(&self, x: i32, z: i32) -> &Biome {
+        self.sample(x, z).biome
+    }
+
+    /// Decoration placed on this column, if any
+    pub fn decoration(&self, biome: &Biome, x: i32, z: i32) -> Option<Decoration> {
+        let decoration = biome.decoration?;
+        let roll = column_hash(self.seed, x, z) as f32 / u32::MAX as f32;
+        if roll < decoration.chance {
+            Some(decoration)
+        } else {
+            None
+        }
+    }
+}
+
+pub(super) fn mix(mut value: u64) -> u64 {
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    value ^ (value >> 31)
+}
+
+/// Stable per-column hash, independent of generation order
+pub(crate) fn column_hash(seed: u32, x: i32, z: i32) -> u32 {
+    mix(mix((seed as u64) << 32 | x as u32 as u64) ^ z as u32 as u64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{
+        block::test_registry,
+        config::{GeneratorConfig, NoiseConfig},
+    };
+
+    fn biome_map(seed: u32) -> BiomeMap {
+        let registry = test_registry();
+        let config = match GeneratorConfig::default() {
+            GeneratorConfig::Noise(config) => config,
+            _ => NoiseConfig::default(),
+        };
+        let biomes = config
+            .biomes
+            .iter()
+            .map(|biome| Biome::from_config(biome, &registry).unwrap())
+            .collect();
+        BiomeMap::new(seed, config.biome_scale, biomes)
+    }
+
+    #[test]
+    fn test_biome_lookup() {
+        let (a, b) = (biome_map(7), biome_map(7));
+        let mut seen = std::collections::HashSet::new();
+        for x in (-4000..4000).step_by(97) {
+            for z in (-4000..4000).step_by(89) {
+                assert_eq!(a.biome_at(x, z).name, b.biome_at(x, z).name);
+                seen.insert(a.biome_at(x, z).name.clone());
+            }
+        }
+        assert!(seen.len() >= 3, "too few biomes: {:?}", seen);
+    }
+
+    #[test]
+    fn test_blend() {
+        let map = biome_map(42);
+        // height scale changes gradually between neighbouring columns,
+        // including across chunk borders
+        for x in -200..200 {
+            let a = map.sample(x, 15).height_scale;
+            let b = map.sample(x, 16).height_scale;
+            let c = map.sample(x + 1, 16).height_scale;
+            assert!((a - b).abs() < 0.05, "step at {}: {} -> {}", x, a, b);
+            assert!((b - c).abs() < 0.05, "step at {}: {} -> {}", x, b, c);
+        }
+    }
+}
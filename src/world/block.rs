@@ -1,54 +1,484 @@
-use std::fmt;
+use std::{collections::HashMap, convert::TryFrom, fmt, num::NonZeroU16, ops::Index, path::Path};
 
-use crate::common::color::*;
+use anyhow::{bail, ensure, Context};
+use serde::Deserialize;
 
-/// BlockType Enum
+use crate::common::{color::Color, direction::Direction};
+
+use super::{fluid::FALLING, light::MAX_LIGHT};
+
+/// Compact block reference handed out by [`BlockRegistry`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockId(NonZeroU16);
+
+impl BlockId {
+    fn from_index(index: usize) -> Self {
+        let raw = u16::try_from(index + 1).expect("too many blocks");
+        Self(NonZeroU16::new(raw).unwrap())
+    }
+
+    pub fn index(self) -> usize {
+        self.0.get() as usize - 1
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// How a block looks, lights and lets things through
 ///
-/// TODO: Add more block type
-#[derive(Debug)]
+/// Every variant has a color, faces of blocks without a texture are drawn
+/// in it.
+#[derive(Debug, Clone, Deserialize)]
 pub enum BlockType {
-    Solid { color: Color },
+    /// Opaque block, the default kind
+    Solid {
+        color: Color,
+        #[serde(default)]
+        texture: Option<BlockTexture>,
+    },
+    /// Light source, rendered without shading
+    Emissive {
+        color: Color,
+        light_level: u8,
+        #[serde(default)]
+        texture: Option<BlockTexture>,
+    },
+    /// Lets light through and keeps faces behind it, like glass or leaves
+    Transparent {
+        color: Color,
+        #[serde(default)]
+        texture: Option<BlockTexture>,
+    },
+    /// Blended over whatever is behind it, like water or tinted glass
+    Translucent {
+        color: Color,
+        /// `0.0..=1.0`, how much of the block color covers the background
+        opacity: f32,
+        #[serde(default)]
+        texture: Option<BlockTexture>,
+    },
+    /// Flows out of source blocks, see [`super::fluid`]
+    Fluid {
+        color: Color,
+        opacity: f32,
+        /// Distance in blocks flowing fluid gets from its source
+        spread: u8,
+        #[serde(default)]
+        texture: Option<BlockTexture>,
+    },
+}
+
+/// Atlas tiles drawn on the faces of a block, by file name without the
+/// extension
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub enum BlockTexture {
+    /// Same tile on every face
+    All(String),
+    /// Tiles for the top, the sides and the bottom, like grass or logs
+    Column {
+        top: String,
+        side: String,
+        bottom: String,
+    },
+}
+
+impl BlockTexture {
+    pub fn tile(&self, direction: Direction) -> &str {
+        match self {
+            BlockTexture::All(tile) => tile,
+            BlockTexture::Column { top, side, bottom } => match direction {
+                Direction::Up => top,
+                Direction::Down => bottom,
+                _ => side,
+            },
+        }
+    }
+}
+
+impl BlockType {
+    /// Block stops light and hides faces of its neighbors
+    pub fn is_opaque(&self) -> bool {
+        match self {
+            BlockType::Solid { .. } | BlockType::Emissive { .. } => true,
+            BlockType::Transparent { .. }
+            | BlockType::Translucent { .. }
+            | BlockType::Fluid { .. } => false,
+        }
+    }
+
+    /// Block has to be drawn blended after everything else
+    pub fn is_translucent(&self) -> bool {
+        self.opacity() < 1.0
+    }
+
+    pub fn is_fluid(&self) -> bool {
+        matches!(self, BlockType::Fluid { .. })
+    }
+
+    /// Block light level emitted by the block
+    pub fn emission(&self) -> u8 {
+        match self {
+            BlockType::Emissive { light_level, .. } => *light_level,
+            _ => 0,
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            BlockType::Solid { color, .. }
+            | BlockType::Emissive { color, .. }
+            | BlockType::Transparent { color, .. }
+            | BlockType::Translucent { color, .. }
+            | BlockType::Fluid { color, .. } => *color,
+        }
+    }
+
+    /// Tiles of a textured block, others are drawn with their color
+    pub fn texture(&self) -> Option<&BlockTexture> {
+        match self {
+            BlockType::Solid { texture, .. }
+            | BlockType::Emissive { texture, .. }
+            | BlockType::Transparent { texture, .. }
+            | BlockType::Translucent { texture, .. }
+            | BlockType::Fluid { texture, .. } => texture.as_ref(),
+        }
+    }
+
+    pub fn opacity(&self) -> f32 {
+        match self {
+            BlockType::Translucent { opacity, .. } | BlockType::Fluid { opacity, .. } => *opacity,
+            _ => 1.0,
+        }
+    }
 }
 
 impl fmt::Display for BlockType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            BlockType::Solid { color } => write!(f, "<solid {color}>", color = color),
+            BlockType::Solid { color, .. } => write!(f, "<solid {color}>", color = color),
+            BlockType::Emissive {
+                color, light_level, ..
+            } => write!(
+                f,
+                "<emissive {color} {level}>",
+                color = color,
+                level = light_level
+            ),
+            BlockType::Transparent { color, .. } => {
+                write!(f, "<transparent {color}>", color = color)
+            }
+            BlockType::Translucent { color, opacity, .. } => write!(
+                f,
+                "<translucent {color} {opacity}>",
+                color = color,
+                opacity = opacity
+            ),
+            BlockType::Fluid {
+                color,
+                opacity,
+                spread,
+                ..
+            } => write!(
+                f,
+                "<fluid {color} {opacity} {spread}>",
+                color = color,
+                opacity = opacity,
+                spread = spread
+            ),
+        }
+    }
+}
+
+/// How entities collide with a block
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Collision {
+    /// Stops movement from every side
+    #[default]
+    Solid,
+    /// No collision at all, like grass, flowers or water
+    Passable,
+    /// No collision, entities inside climb instead of falling
+    Climbable,
+    /// Only stops entities landing on its top
+    Platform,
+}
+
+/// What a block does when picked by a random tick
+#[derive(Debug, Clone, Deserialize)]
+pub enum RandomTick {
+    /// Turn a nearby `onto` block with air above into this block
+    Spread { onto: String },
+    /// Turn into the next stage
+    Grow { into: String },
+    /// Turn into `into` (air for `None`) unless a `support` block is within
+    /// `radius`
+    Decay {
+        into: Option<String>,
+        support: String,
+        radius: i32,
+    },
+}
+
+impl RandomTick {
+    fn names(&self) -> Vec<&str> {
+        match self {
+            RandomTick::Spread { onto } => vec![onto],
+            RandomTick::Grow { into } => vec![into],
+            RandomTick::Decay { into, support, .. } => {
+                into.iter().map(String::as_str).chain(Some(support.as_str())).collect()
+            }
         }
     }
 }
 
+/// Block behaviour flags
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct BlockFlags {
+    /// Player cannot break this block
+    pub unbreakable: bool,
+    /// Block falls down when there's nothing below it
+    pub falls: bool,
+}
+
 /// Block data(name and data)
-#[derive(Debug)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Block {
     /// Block name
-    pub name: &'static str,
+    pub name: String,
     /// Block data
     pub data: BlockType,
+    /// Block flags
+    #[serde(default)]
+    pub flags: BlockFlags,
+    #[serde(default)]
+    pub collision: Collision,
+    #[serde(default)]
+    pub random_tick: Option<RandomTick>,
 }
 
 impl fmt::Display for Block {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let Block { name, data } = self;
-        write!(f, "<{name}: {data}>", name = *name, data = data)
+        let Block { name, data, .. } = self;
+        write!(f, "<{name}: {data}>", name = name, data = data)
     }
 }
 
-#[allow(dead_code)]
-pub mod constants {
+/// Block definitions loaded at startup
+#[derive(Debug, Clone)]
+pub struct BlockRegistry {
+    blocks: Vec<Block>,
+    lookup: HashMap<String, BlockId>,
+}
+
+impl BlockRegistry {
+    pub fn new(blocks: Vec<Block>) -> anyhow::Result<Self> {
+        ensure!(
+            blocks.len() < u16::MAX as usize,
+            "too many blocks ({})",
+            blocks.len()
+        );
+        let mut lookup = HashMap::with_capacity(blocks.len());
+        for (index, block) in blocks.iter().enumerate() {
+            match block.data {
+                BlockType::Emissive { light_level, .. } => ensure!(
+                    light_level <= MAX_LIGHT,
+                    "light level {} of {:?} above {}",
+                    light_level,
+                    block.name,
+                    MAX_LIGHT
+                ),
+                BlockType::Translucent { opacity, .. } | BlockType::Fluid { opacity, .. } => {
+                    ensure!(
+                        (0.0..=1.0).contains(&opacity),
+                        "opacity {} of {:?} out of range",
+                        opacity,
+                        block.name
+                    )
+                }
+                _ => {}
+            }
+            if let BlockType::Fluid { spread, .. } = block.data {
+                ensure!(
+                    spread < FALLING,
+                    "spread {} of {:?} too far",
+                    spread,
+                    block.name
+                );
+            }
+            if lookup
+                .insert(block.name.clone(), BlockId::from_index(index))
+                .is_some()
+            {
+                bail!("duplicated block name {:?}", block.name);
+            }
+        }
+        for block in &blocks {
+            for name in block.random_tick.iter().flat_map(RandomTick::names) {
+                ensure!(
+                    lookup.contains_key(name),
+                    "unknown block name {:?} in random tick of {:?}",
+                    name,
+                    block.name
+                );
+            }
+        }
+        Ok(Self { blocks, lookup })
+    }
+
+    pub fn from_ron(source: &str) -> anyhow::Result<Self> {
+        let blocks: Vec<Block> = ron::from_str(source).context("invalid block definitions")?;
+        Self::new(blocks)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read block definitions {}", path.display()))?;
+        Self::from_ron(&source)
+            .with_context(|| format!("failed to load block definitions {}", path.display()))
+    }
+
+    pub fn find(&self, name: &str) -> Option<BlockId> {
+        self.lookup.get(name).copied()
+    }
+
+    /// Resolve block name, unknown name is reported as error
+    pub fn lookup(&self, name: &str) -> anyhow::Result<BlockId> {
+        self.find(name)
+            .with_context(|| format!("unknown block name {:?}", name))
+    }
+
+    pub fn lookup_all<S: AsRef<str>>(&self, names: &[S]) -> anyhow::Result<Vec<BlockId>> {
+        names
+            .iter()
+            .map(|name| self.lookup(name.as_ref()))
+            .collect()
+    }
+
+    /// Face of `block` towards `neighbor` can't be seen
+    ///
+    /// Opaque neighbors hide every face, other blocks only hide faces of the
+    /// same block, so the inside of a glass wall or a lake isn't drawn.
+    pub fn is_face_hidden(&self, block: BlockId, neighbor: Option<BlockId>) -> bool {
+        match neighbor {
+            Some(neighbor) => neighbor == block || self[neighbor].data.is_opaque(),
+            None => false,
+        }
+    }
+
+    pub fn iter(&self) -> impl '_ + Iterator<Item = (BlockId, &'_ Block)> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .map(|(index, block)| (BlockId::from_index(index), block))
+    }
+}
+
+impl Index<BlockId> for BlockRegistry {
+    type Output = Block;
+
+    fn index(&self, index: BlockId) -> &Self::Output {
+        &self.blocks[index.index()]
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn test_registry() -> BlockRegistry {
+    BlockRegistry::from_ron(include_str!("../../assets/blocks.ron")).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
     use super::*;
+    use crate::common::color::YELLOW;
 
-    const fn solid_block(name: &'static str, color: Color) -> Block {
-        Block {
-            name,
-            data: BlockType::Solid { color },
+    #[test]
+    fn test_lookup() {
+        let registry = test_registry();
+        let id = registry.lookup("yellow").unwrap();
+        assert_eq!(registry[id].name, "yellow");
+        match registry[id].data {
+            BlockType::Solid { color, .. } => assert_eq!(color, YELLOW),
+            ref data => panic!("unexpected block type {}", data),
         }
+        let err = registry.lookup("missing").unwrap_err();
+        assert!(err.to_string().contains("\"missing\""));
     }
 
-    pub const GREEN_BLOCK: &'static Block = &solid_block("green", GREEN);
-    pub const RED_BLOCK: &'static Block = &solid_block("red", RED);
-    pub const BLUE_BLOCK: &'static Block = &solid_block("blue", BLUE);
-    pub const PURPLE_BLOCK: &'static Block = &solid_block("purple", PURPLE);
-    pub const YELLOW_BLOCK: &'static Block = &solid_block("yellow", YELLOW);
-    pub const AQUA_BLOCK: &'static Block = &solid_block("aqua", AQUA);
+    #[test]
+    fn test_texture() {
+        let registry = test_registry();
+        let data = |name| &registry[registry.lookup(name).unwrap()].data;
+        let grass = data("grass").texture().unwrap();
+        assert_eq!(grass.tile(Direction::Up), "grass_top");
+        assert_eq!(grass.tile(Direction::North), "grass_side");
+        assert_eq!(grass.tile(Direction::Down), "dirt");
+        let stone = data("stone").texture().unwrap();
+        assert_eq!(stone.tile(Direction::East), "stone");
+        assert!(data("yellow").texture().is_none());
+    }
+
+    #[test]
+    fn test_duplicated() {
+        let source = r#"[
+            (name: "a", data: Solid(color: (1, 2, 3))),
+            (name: "a", data: Solid(color: (4, 5, 6)), flags: (unbreakable: true)),
+        ]"#;
+        assert!(BlockRegistry::from_ron(source).is_err());
+    }
+
+    #[test]
+    fn test_face_culling() {
+        let registry = test_registry();
+        let (stone, glass, water) = (
+            registry.find("stone"),
+            registry.find("glass"),
+            registry.find("water"),
+        );
+        let hidden =
+            |block: Option<BlockId>, neighbor| registry.is_face_hidden(block.unwrap(), neighbor);
+        assert!(hidden(stone, stone));
+        assert!(hidden(glass, stone));
+        assert!(hidden(water, stone));
+        assert!(!hidden(stone, glass));
+        assert!(!hidden(stone, water));
+        assert!(!hidden(stone, None));
+        assert!(hidden(glass, glass));
+        assert!(hidden(water, water));
+        assert!(!hidden(glass, water));
+        assert!(!hidden(water, glass));
+    }
+
+    #[test]
+    fn test_collision() {
+        let registry = test_registry();
+        let collision = |name| registry[registry.lookup(name).unwrap()].collision;
+        assert_eq!(collision("stone"), Collision::Solid);
+        assert_eq!(collision("tall_grass"), Collision::Passable);
+        assert_eq!(collision("ladder"), Collision::Climbable);
+        assert_eq!(collision("platform"), Collision::Platform);
+    }
+
+    #[test]
+    fn test_random_tick() {
+        let registry = test_registry();
+        let grass = &registry[registry.lookup("grass").unwrap()];
+        assert!(matches!(grass.random_tick, Some(RandomTick::Spread { .. })));
+        let source = r#"[(name: "a", data: Solid(color: (1, 2, 3)), random_tick: Some(Grow(into: "b")))]"#;
+        assert!(BlockRegistry::from_ron(source).is_err());
+    }
+
+    #[test]
+    fn test_emissive() {
+        let registry = test_registry();
+        let lamp = &registry[registry.lookup("lamp").unwrap()];
+        assert!(lamp.data.emission() > 0);
+        let source = "[(name: \"a\", data: Emissive(color: (1, 2, 3), light_level: 16))]";
+        assert!(BlockRegistry::from_ron(source).is_err());
+    }
 }
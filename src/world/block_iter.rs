@@ -1,5 +1,3 @@
-use std::ops::Range;
-
 use strum::IntoEnumIterator;
 
 use crate::{
@@ -7,13 +5,11 @@ use crate::{
     math::axis::{Axis, ExtractAxis, HasAxisMut, HasAxisMutExt, MapAxisExt, SortAxisExt},
 };
 
-use super::{
-    chunk::{BlockSubPos, Chunk},
-    ChunkPos, MapSize,
-};
+use super::{chunk::BlockSubPos, convert_pos, ChunkPos, WORLD_HEIGHT};
 
+/// Voxel ray traversal, the world is unbounded on x/z and clipped to the
+/// chunk height on y
 pub struct BlockIter {
-    size: MapSize,
     position: glam::Vec3A,
     next_offset: glam::Vec3A,
     delta: glam::Vec3A,
@@ -22,92 +18,47 @@ pub struct BlockIter {
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct BlockIterResult {
-    pub fine_position: glam::UVec3,
+    pub fine_position: glam::IVec3,
     pub direction: Direction,
     pub length: f32,
 }
 
-fn is_origin_inside(size: MapSize, position: glam::Vec3A) -> bool {
-    let (x, y, z) = position.into();
-    let (world_max_x, world_max_y, world_max_z) = size.to_world_size().into();
-    (0.0..=world_max_x as f32).contains(&x)
-        && (0.0..=world_max_y as f32).contains(&y)
-        && (0.0..=world_max_z as f32).contains(&z)
+fn is_origin_inside(position: glam::Vec3A) -> bool {
+    (0.0..=WORLD_HEIGHT as f32).contains(&position.y)
 }
 
-fn is_position_inside(size: MapSize, position: glam::Vec3A) -> bool {
-    let (x, y, z) = position.into();
-    let (world_max_x, world_max_y, world_max_z) = size.to_world_size().into();
-    (0.0..world_max_x as f32).contains(&x)
-        && (0.0..world_max_y as f32).contains(&y)
-        && (0.0..world_max_z as f32).contains(&z)
+fn is_position_inside(position: glam::Vec3A) -> bool {
+    (0.0..WORLD_HEIGHT as f32).contains(&position.y)
 }
 
-fn merge_range(origin: &mut Range<f32>, rhs: Range<f32>) -> bool {
-    if rhs.end - origin.start > f32::EPSILON && origin.end - rhs.start > f32::EPSILON {
-        *origin = origin.start.max(rhs.start)..origin.end.min(rhs.end);
-        true
-    } else {
-        false
+fn get_origin_point(position: glam::Vec3A, direction: glam::Vec3A) -> Option<(glam::Vec3A, f32)> {
+    if is_origin_inside(position) {
+        return Some((position, 0.0));
     }
-}
-
-fn gen_range(a: f32, b: f32) -> Range<f32> {
-    a.min(b)..a.max(b)
-}
-
-fn get_origin_point(
-    size: MapSize,
-    position: glam::Vec3A,
-    direction: glam::Vec3A,
-) -> Option<(glam::Vec3A, f32)> {
-    if is_origin_inside(size, position) {
-        Some((position, 0.0))
+    if direction.y == 0.0 {
+        return None;
+    }
+    let invspeed = 1.0 / direction.y;
+    let tmin = ((-position.y) * invspeed).min((WORLD_HEIGHT as f32 - position.y) * invspeed);
+    if tmin > 0.0 {
+        Some((position + direction * tmin, tmin))
     } else {
-        let world_size = size.to_world_size();
-        let mut range = f32::NEG_INFINITY..f32::INFINITY;
-        for axis in direction.sort_axis(|axis, val| val > axis) {
-            let base = position.extract_axis(axis);
-            let invspeed = 1.0 / direction.extract_axis(axis);
-            let bound = world_size.extract_axis(axis) as f32;
-            let tmp = gen_range((-base) * invspeed, (bound - base) * invspeed);
-            if !merge_range(&mut range, tmp) {
-                return None;
-            }
-        }
-        let tmin = range.start;
-        if tmin > 0.0 {
-            let new_position = position + direction * tmin;
-            Some((new_position, tmin))
-        } else {
-            None
-        }
+        None
     }
 }
 
-fn extract_fine_position(position: glam::Vec3A, is_negative: [bool; 3]) -> glam::UVec3 {
+fn extract_fine_position(position: glam::Vec3A, is_negative: [bool; 3]) -> glam::IVec3 {
     Axis::generate(|axis| {
         let dir = is_negative.extract_axis(axis);
         let pos = position.extract_axis(axis);
         if dir && pos.fract() == 0.0 {
-            (pos - 1.0) as u32
+            pos as i32 - 1
         } else {
-            pos as u32
+            pos.floor() as i32
         }
     })
 }
 
-fn extract_position(fine_position: glam::UVec3) -> (ChunkPos, BlockSubPos) {
-    use Axis::*;
-    let fine_pos: [usize; 3] = fine_position.map_axis(|_, val| val as usize);
-    let cx = (fine_pos.extract_axis(X) / Chunk::WIDTH) as u8;
-    let cz = (fine_pos.extract_axis(Z) / Chunk::WIDTH) as u8;
-    let bx = (fine_pos.extract_axis(X) % Chunk::WIDTH) as u8;
-    let by = fine_pos.extract_axis(Y) as u8;
-    let bz = (fine_pos.extract_axis(Z) % Chunk::WIDTH) as u8;
-    (ChunkPos(cx, cz), BlockSubPos::new(bx, by, bz))
-}
-
 fn get_next_offset_delta(
     origin: glam::Vec3A,
     direction: glam::Vec3A,
@@ -134,15 +85,14 @@ fn get_next_offset_delta(
 }
 
 impl BlockIter {
-    pub fn new(size: MapSize, start_position: glam::Vec3A, direction: glam::Vec3A) -> Option<Self> {
+    pub fn new(start_position: glam::Vec3A, direction: glam::Vec3A) -> Option<Self> {
         let direction = match direction.try_normalize() {
             Some(direction) => direction,
             None => return Default::default(),
         };
-        get_origin_point(size, start_position, direction).map(|(origin_point, length)| {
+        get_origin_point(start_position, direction).map(|(origin_point, length)| {
             let (next_offset, delta) = get_next_offset_delta(origin_point, direction);
             Self {
-                size,
                 position: extract_fine_position(
                     origin_point,
                     direction.map_axis(|_, val| val < 0.0),
@@ -185,9 +135,9 @@ impl Iterator for BlockIter {
     fn next(&mut self) -> Option<Self::Item> {
         let min_axis = self.next_offset.sort_axis(|a, b| a < b).next().unwrap();
         let direction = self.step(self.next_offset.extract_axis(min_axis));
-        if is_position_inside(self.size, self.position) {
+        if is_position_inside(self.position) {
             Some(BlockIterResult {
-                fine_position: self.position.map_axis(|_, val| val as u32),
+                fine_position: self.position.map_axis(|_, val| val as i32),
                 direction,
                 length: self.length,
             })
@@ -199,70 +149,70 @@ impl Iterator for BlockIter {
 
 impl BlockIterResult {
     pub fn get_position(self) -> (ChunkPos, BlockSubPos) {
-        extract_position(self.fine_position)
+        // results are always inside the chunk height
+        convert_pos(self.fine_position).unwrap()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::world::chunk::Chunk;
 
     #[test]
     fn test_get_origin_point() {
-        let size = MapSize::new((1, 1));
         let position = glam::vec3a(-1.0, -1.0, -1.0);
         let direction = glam::vec3a(1.0, 1.0, 1.0);
         let direction = direction.normalize();
         assert_eq!(
-            get_origin_point(size, position, direction),
+            get_origin_point(position, direction),
             Some((glam::vec3a(0.0, 0.0, 0.0), 1.7320509))
         );
         {
             let position = glam::vec3a(1.0, -1.0, 1.0);
             assert_eq!(
-                get_origin_point(size, position, direction),
+                get_origin_point(position, direction),
                 Some((glam::vec3a(2.0, 0.0, 2.0), 1.7320509))
             );
         }
         {
             let position = glam::vec3a(1.0, 1.0, 1.0);
             assert_eq!(
-                get_origin_point(size, position, direction),
+                get_origin_point(position, direction),
                 Some((glam::vec3a(1.0, 1.0, 1.0), 0.0))
             );
         }
         {
             let direction = glam::vec3a(1.0, -1.0, 1.0);
             let direction = direction.normalize();
-            assert_eq!(get_origin_point(size, position, direction), None);
+            assert_eq!(get_origin_point(position, direction), None);
         }
         {
             let position = glam::vec3a(-1.0, -1.0, 0.0);
             let direction = glam::vec3a(1.0, 2.0, 1.0);
             let direction = direction.normalize();
             assert_eq!(
-                get_origin_point(size, position, direction),
-                Some((glam::vec3a(0.0, 1.0, 1.0), 2.4494898))
+                get_origin_point(position, direction),
+                Some((glam::vec3a(-0.5, 0.0, 0.5), 1.2247449))
             );
         }
         {
-            let world_size = size.to_world_size();
             let position = glam::vec3a(
-                world_size.x as f32 + 0.0,
-                world_size.y as f32 + 1.0,
-                world_size.z as f32 + 0.0,
+                Chunk::WIDTH as f32,
+                WORLD_HEIGHT as f32 + 1.0,
+                Chunk::WIDTH as f32,
             );
             let direction = glam::vec3a(-1.0, -2.0, -1.0);
             let direction = direction.normalize();
             const BOUND: f32 = (Chunk::WIDTH as f32) - 0.5;
             assert_eq!(
-                get_origin_point(size, position, direction),
-                Some((glam::vec3a(BOUND, 64.0, BOUND), 1.2247449))
+                get_origin_point(position, direction),
+                Some((glam::vec3a(BOUND, WORLD_HEIGHT as f32, BOUND), 1.2247449))
             );
         }
         {
             let position = glam::vec3a(1.0, 1024.0, 1.0);
-            assert_eq!(get_origin_point(size, position, direction), None);
+            assert_eq!(get_origin_point(position, direction), None);
         }
     }
 
@@ -290,89 +240,79 @@ mod tests {
 
     #[test]
     fn test_iter() {
-        let mut iter = BlockIter::new(
-            MapSize::new((1, 1)),
-            glam::vec3a(0.5, 0.5, 0.5),
-            glam::vec3a(1.0, 0.0, 0.0),
-        )
-        .unwrap();
+        let mut iter =
+            BlockIter::new(glam::vec3a(0.5, 0.5, 0.5), glam::vec3a(1.0, 0.0, 0.0)).unwrap();
         assert_eq!(
             iter.next().unwrap().get_position(),
-            (ChunkPos(0, 0), BlockSubPos::new(1, 0, 0))
+            (ChunkPos(0, 0, 0), BlockSubPos::new(1, 0, 0))
         );
         assert_eq!(
             iter.next().unwrap().get_position(),
-            (ChunkPos(0, 0), BlockSubPos::new(2, 0, 0))
+            (ChunkPos(0, 0, 0), BlockSubPos::new(2, 0, 0))
         );
         assert_eq!(iter.next().unwrap().length, 2.5);
 
-        let mut iter = BlockIter::new(
-            MapSize::new((1, 1)),
-            glam::vec3a(0.5, 0.5, 0.5),
-            glam::vec3a(-1.0, 0.0, 0.0),
-        )
-        .unwrap();
+        let mut iter =
+            BlockIter::new(glam::vec3a(0.5, 0.5, 0.5), glam::vec3a(-1.0, 0.0, 0.0)).unwrap();
+        assert_eq!(
+            iter.next().unwrap().get_position(),
+            (ChunkPos(-1, 0, 0), BlockSubPos::new(15, 0, 0))
+        );
+
+        let mut iter =
+            BlockIter::new(glam::vec3a(0.5, 0.5, 0.5), glam::vec3a(0.0, -1.0, 0.0)).unwrap();
         assert_eq!(iter.next(), None);
 
-        let mut iter = BlockIter::new(
-            MapSize::new((1, 1)),
-            glam::vec3a(0.5, 0.5, 0.5),
-            glam::vec3a(1.0, 1.0, 1.0),
-        )
-        .unwrap();
+        let mut iter =
+            BlockIter::new(glam::vec3a(0.5, 0.5, 0.5), glam::vec3a(1.0, 1.0, 1.0)).unwrap();
         assert_eq!(
             iter.next().unwrap().get_position(),
-            (ChunkPos(0, 0), BlockSubPos::new(1, 0, 0))
+            (ChunkPos(0, 0, 0), BlockSubPos::new(1, 0, 0))
         );
         assert_eq!(
             iter.next().unwrap().get_position(),
-            (ChunkPos(0, 0), BlockSubPos::new(1, 1, 0))
+            (ChunkPos(0, 0, 0), BlockSubPos::new(1, 1, 0))
         );
         assert_eq!(
             iter.next().unwrap().get_position(),
-            (ChunkPos(0, 0), BlockSubPos::new(1, 1, 1))
+            (ChunkPos(0, 0, 0), BlockSubPos::new(1, 1, 1))
         );
 
-        let mut iter = BlockIter::new(
-            MapSize::new((1, 1)),
-            glam::vec3a(0.5, 0.5, 0.5),
-            glam::vec3a(0.2, 0.0, 1.0),
-        )
-        .unwrap();
+        let mut iter =
+            BlockIter::new(glam::vec3a(0.5, 0.5, 0.5), glam::vec3a(0.2, 0.0, 1.0)).unwrap();
         assert_eq!(
             iter.next().unwrap().get_position(),
-            (ChunkPos(0, 0), BlockSubPos::new(0, 0, 1))
+            (ChunkPos(0, 0, 0), BlockSubPos::new(0, 0, 1))
         );
     }
 
     #[test]
     fn test_bug() {
-        let mut iter = BlockIter::new(
-            MapSize::new((1, 1)),
-            glam::vec3a(5.0, 5.0, 5.0),
-            glam::vec3a(-0.3, 0.0, 0.12),
-        )
-        .unwrap();
+        let mut iter =
+            BlockIter::new(glam::vec3a(5.0, 5.0, 5.0), glam::vec3a(-0.3, 0.0, 0.12)).unwrap();
         assert_eq!(
             iter.next().unwrap().get_position(),
-            (ChunkPos(0, 0), BlockSubPos::new(3, 5, 5))
+            (ChunkPos(0, 0, 0), BlockSubPos::new(3, 5, 5))
         );
         assert_eq!(
             iter.next().unwrap().get_position(),
-            (ChunkPos(0, 0), BlockSubPos::new(2, 5, 5))
+            (ChunkPos(0, 0, 0), BlockSubPos::new(2, 5, 5))
         );
         assert_eq!(
             iter.next().unwrap().get_position(),
-            (ChunkPos(0, 0), BlockSubPos::new(2, 5, 6))
+            (ChunkPos(0, 0, 0), BlockSubPos::new(2, 5, 6))
         );
         assert_eq!(
             iter.next().unwrap().get_position(),
-            (ChunkPos(0, 0), BlockSubPos::new(1, 5, 6))
+            (ChunkPos(0, 0, 0), BlockSubPos::new(1, 5, 6))
         );
         assert_eq!(
             iter.next().unwrap().get_position(),
-            (ChunkPos(0, 0), BlockSubPos::new(0, 5, 6))
+            (ChunkPos(0, 0, 0), BlockSubPos::new(0, 5, 6))
+        );
+        assert_eq!(
+            iter.next().unwrap().get_position(),
+            (ChunkPos(-1, 0, 0), BlockSubPos::new(15, 5, 6))
         );
-        assert_eq!(iter.next(), None);
     }
 }
@@ -4,24 +4,51 @@ use std::{
     convert::{TryFrom, TryInto},
     fmt,
     ops::{Add, Index, IndexMut},
-    sync::atomic::{AtomicBool, Ordering},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
     usize,
 };
 
-use crate::common::{color::Color, direction::Direction};
+use crate::{
+    common::direction::Direction,
+    math::axis::{HasAxis, HasAxisMut},
+};
 
-use super::block::{Block, BlockType};
+use super::{
+    block::{BlockId, BlockRegistry, BlockType},
+    fluid::FluidLevel,
+    light::Light,
+    palette::PalettedArray,
+};
 
 const WIDTH: usize = 16;
-const HEIGHT: usize = 64;
+const HEIGHT: usize = 16;
 
 pub const CHUNK_SIZE: usize = WIDTH * WIDTH * HEIGHT;
 
-/// 16 * 16 * 64 block storage
+/// 16 * 16 * 16 block storage, a section of a chunk column
+///
+/// Blocks are kept in a [`PalettedArray`] behind `Index`/`IndexMut`,
+/// [`Chunk::set`] also returns the previous block. Blocks written in place
+/// through `IndexMut` or `iter_mut` are stored with [`Chunk::set`] before the
+/// next change, reads see them right away. Light levels are kept in their own
+/// palette but never saved, they are recomputed when the chunk is loaded.
+/// Fluid levels are kept the same way and saved along with the blocks.
+///
+/// Edits grow a [`DirtyRegion`] so the renderer only has to remesh the
+/// layers around them.
 #[derive(Debug)]
 pub struct Chunk {
-    dirty: AtomicBool,
-    data: [Option<&'static Block>; CHUNK_SIZE],
+    dirty: Mutex<Option<DirtyRegion>>,
+    revision: u64,
+    data: PalettedArray,
+    edit: Option<Edit>,
+    /// Changes in `edit` were seen by [`Chunk::mark_clean`]
+    edit_clean: AtomicBool,
+    light: PalettedArray<Light>,
+    fluid: PalettedArray<FluidLevel>,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -39,6 +66,20 @@ impl BlockSubPos {
     fn as_index(self) -> usize {
         self.0
     }
+
+    /// Step into the neighbor chunk, position on the opposite face
+    pub fn wrapping_add(self, rhs: Direction) -> Self {
+        let (x, y, z) = self.into();
+        let (w, h) = (WIDTH as u8, HEIGHT as u8);
+        match rhs {
+            Direction::North => Self::new(x, y, (z + w - 1) % w),
+            Direction::South => Self::new(x, y, (z + 1) % w),
+            Direction::East => Self::new((x + 1) % w, y, z),
+            Direction::West => Self::new((x + w - 1) % w, y, z),
+            Direction::Up => Self::new(x, (y + 1) % h, z),
+            Direction::Down => Self::new(x, (y + h - 1) % h, z),
+        }
+    }
 }
 
 impl TryFrom<(u8, u8, u8)> for BlockSubPos {
@@ -83,39 +124,163 @@ impl fmt::Display for BlockSubPos {
     }
 }
 
+/// Revisions come from one counter shared by all chunks, so a newer
+/// revision always means newer content, even for a chunk loaded again
+fn next_revision() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Box of blocks changed since the chunk was last meshed, in chunk local
+/// coordinates with inclusive bounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRegion {
+    pub min: glam::IVec3,
+    pub max: glam::IVec3,
+}
+
+impl DirtyRegion {
+    /// The whole chunk
+    pub fn all() -> Self {
+        Self {
+            min: glam::IVec3::ZERO,
+            max: glam::ivec3(WIDTH as i32 - 1, HEIGHT as i32 - 1, WIDTH as i32 - 1),
+        }
+    }
+
+    pub fn block(pos: BlockSubPos) -> Self {
+        let (x, y, z) = pos.into();
+        let pos = glam::ivec3(x as i32, y as i32, z as i32);
+        Self { min: pos, max: pos }
+    }
+
+    /// Layer of blocks on the `direction` side of the chunk
+    pub fn face(direction: Direction) -> Self {
+        let mut region = Self::all();
+        let axis = direction.axis();
+        if direction.is_positive() {
+            region.min.set_axis(axis, *region.max.get_axis(axis));
+        } else {
+            region.max.set_axis(axis, 0);
+        }
+        region
+    }
+
+    /// Smallest region containing both
+    pub fn union(self, rhs: Self) -> Self {
+        Self {
+            min: self.min.min(rhs.min),
+            max: self.max.max(rhs.max),
+        }
+    }
+}
+
+/// Blocks handed out to be written in place
+#[derive(Debug)]
+struct Edit {
+    /// Index of the first block
+    start: usize,
+    blocks: Vec<Option<BlockId>>,
+    /// Revision of the chunk if any block changed
+    revision: u64,
+}
+
+impl Edit {
+    fn get(&self, index: usize) -> Option<&Option<BlockId>> {
+        self.blocks.get(index.checked_sub(self.start)?)
+    }
+}
+
 impl Chunk {
     pub const WIDTH: usize = WIDTH;
     pub const HEIGHT: usize = HEIGHT;
 
-    fn mark_dirty(&mut self) {
-        *self.dirty.get_mut() = true;
+    /// Mark the whole chunk for remeshing
+    pub fn mark_dirty(&mut self) {
+        self.mark_region_dirty(DirtyRegion::all());
     }
 
-    pub const fn max_face_count() -> usize {
-        3 * WIDTH * WIDTH * HEIGHT + WIDTH * WIDTH + 2 * WIDTH * HEIGHT
+    /// Grow the dirty region to contain `region`, every change bumps the
+    /// revision
+    pub fn mark_region_dirty(&mut self, region: DirtyRegion) {
+        self.revision = next_revision();
+        let dirty = self.dirty.get_mut().unwrap();
+        *dirty = Some(match *dirty {
+            Some(dirty) => dirty.union(region),
+            None => region,
+        });
     }
 
-    pub fn iter(&self) -> impl '_ + Iterator<Item = (BlockSubPos, &'_ Option<&'static Block>)> {
-        self.data
-            .iter()
-            .enumerate()
-            .map(|(id, blk)| (BlockSubPos(id), blk))
+    fn block(&self, index: usize) -> &Option<BlockId> {
+        match self.edit.as_ref().and_then(|edit| edit.get(index)) {
+            Some(blk) => blk,
+            None => self.data.get(index),
+        }
+    }
+
+    /// Indices the pending edit changes
+    fn edited(&self) -> impl '_ + Iterator<Item = usize> {
+        self.edit.iter().flat_map(move |edit| {
+            (edit.start..)
+                .zip(&edit.blocks)
+                .filter(move |&(id, blk)| self.data.get(id) != blk)
+                .map(|(id, _)| id)
+        })
+    }
+
+    fn is_edited(&self, index: usize) -> bool {
+        match self.edit.as_ref().and_then(|edit| edit.get(index)) {
+            Some(blk) => self.data.get(index) != blk,
+            None => false,
+        }
+    }
+
+    /// Hand out `len` blocks from `start` to write in place, storing the
+    /// previous edit first
+    fn begin_edit(&mut self, start: usize, len: usize) -> &mut [Option<BlockId>] {
+        self.commit();
+        let blocks = (start..start + len).map(|id| *self.data.get(id)).collect();
+        *self.edit_clean.get_mut() = false;
+        let edit = self.edit.insert(Edit {
+            start,
+            blocks,
+            revision: next_revision(),
+        });
+        &mut edit.blocks
+    }
+
+    /// Store blocks changed through the pending edit
+    fn commit(&mut self) {
+        if let Some(edit) = self.edit.take() {
+            for (id, blk) in (edit.start..).zip(edit.blocks) {
+                if *self.data.get(id) != blk {
+                    self.set(BlockSubPos(id), blk);
+                }
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl '_ + Iterator<Item = (BlockSubPos, &'_ Option<BlockId>)> {
+        self.data.iter().enumerate().map(move |(id, blk)| {
+            match self.edit.as_ref().and_then(|edit| edit.get(id)) {
+                Some(edited) => (BlockSubPos(id), edited),
+                None => (BlockSubPos(id), blk),
+            }
+        })
     }
 
     pub fn par_iter(
         &self,
-    ) -> impl '_ + ParallelIterator<Item = (BlockSubPos, &'_ Option<&'static Block>)> {
-        self.data
-            .par_iter()
-            .enumerate()
-            .map(|(id, blk)| (BlockSubPos(id), blk))
+    ) -> impl '_ + ParallelIterator<Item = (BlockSubPos, &'_ Option<BlockId>)> {
+        (0..CHUNK_SIZE)
+            .into_par_iter()
+            .map(move |id| (BlockSubPos(id), self.block(id)))
     }
 
     pub fn iter_mut(
         &mut self,
-    ) -> impl '_ + Iterator<Item = (BlockSubPos, &'_ mut Option<&'static Block>)> {
-        self.mark_dirty();
-        self.data
+    ) -> impl '_ + Iterator<Item = (BlockSubPos, &'_ mut Option<BlockId>)> {
+        self.begin_edit(0, CHUNK_SIZE)
             .iter_mut()
             .enumerate()
             .map(|(id, blk)| (BlockSubPos(id), blk))
@@ -123,61 +288,188 @@ impl Chunk {
 
     pub fn par_iter_mut(
         &mut self,
-    ) -> impl '_ + ParallelIterator<Item = (BlockSubPos, &'_ mut Option<&'static Block>)> {
-        self.mark_dirty();
-        self.data
+    ) -> impl '_ + ParallelIterator<Item = (BlockSubPos, &'_ mut Option<BlockId>)> {
+        self.begin_edit(0, CHUNK_SIZE)
             .par_iter_mut()
             .enumerate()
             .map(|(id, blk)| (BlockSubPos(id), blk))
     }
 
-    pub fn iter_solid(&self) -> impl '_ + Iterator<Item = (BlockSubPos, Color)> {
-        self.iter().filter_map(|(id, blk)| {
-            blk.map(|blk| match blk.data {
-                BlockType::Solid { color } => (id, color),
-            })
-        })
+    /// Replace block at position, returns the previous block
+    ///
+    /// The fluid level is reset, so a placed fluid becomes a source. Writing
+    /// the same block to a source leaves the chunk clean.
+    pub fn set(&mut self, pos: BlockSubPos, blk: Option<BlockId>) -> Option<BlockId> {
+        self.commit();
+        if self[pos] == blk && self.fluid.get(pos.as_index()).is_source() {
+            return blk;
+        }
+        self.mark_region_dirty(DirtyRegion::block(pos));
+        self.fluid.set(pos.as_index(), FluidLevel::SOURCE);
+        self.data.set(pos.as_index(), blk)
     }
 
-    pub fn par_iter_solid(&self) -> impl '_ + ParallelIterator<Item = (BlockSubPos, Color)> {
-        self.par_iter().filter_map(|(id, blk)| {
-            // log::info!("id -> {} = {:?}", id, id);
-            blk.map(|blk| match blk.data {
-                BlockType::Solid { color } => (id, color),
-            })
-        })
+    pub fn iter_solid<'a>(
+        &'a self,
+        registry: &'a BlockRegistry,
+    ) -> impl 'a + Iterator<Item = (BlockSubPos, &'a BlockType)> {
+        self.iter()
+            .filter_map(move |(id, blk)| blk.map(|blk| (id, &registry[blk].data)))
+    }
+
+    pub fn par_iter_solid<'a>(
+        &'a self,
+        registry: &'a BlockRegistry,
+    ) -> impl 'a + ParallelIterator<Item = (BlockSubPos, &'a BlockType)> {
+        self.par_iter()
+            .filter_map(move |(id, blk)| blk.map(|blk| (id, &registry[blk].data)))
+    }
+
+    pub fn light(&self, pos: BlockSubPos) -> Light {
+        *self.light.get(pos.as_index())
+    }
+
+    /// Replace light at position, the chunk is only marked dirty on change
+    pub fn set_light(&mut self, pos: BlockSubPos, light: Light) -> Light {
+        let prev = self.light.set(pos.as_index(), light);
+        if prev != light {
+            self.mark_region_dirty(DirtyRegion::block(pos));
+        }
+        prev
+    }
+
+    pub fn clear_light(&mut self) {
+        self.light = PalettedArray::filled(CHUNK_SIZE, Light::default());
+        self.mark_dirty();
+    }
+
+    pub fn fluid(&self, pos: BlockSubPos) -> FluidLevel {
+        if self.is_edited(pos.as_index()) {
+            // the block is replaced, which resets the level
+            return FluidLevel::SOURCE;
+        }
+        *self.fluid.get(pos.as_index())
+    }
+
+    /// Replace fluid level at position, the chunk is only marked dirty on
+    /// change
+    pub fn set_fluid(&mut self, pos: BlockSubPos, level: FluidLevel) -> FluidLevel {
+        self.commit();
+        let prev = self.fluid.set(pos.as_index(), level);
+        if prev != level {
+            self.mark_region_dirty(DirtyRegion::block(pos));
+        }
+        prev
+    }
+
+    /// Positions with a fluid level other than [`FluidLevel::SOURCE`]
+    pub fn iter_fluid(&self) -> impl '_ + Iterator<Item = (BlockSubPos, FluidLevel)> {
+        self.fluid
+            .iter()
+            .enumerate()
+            .filter(move |&(id, level)| !level.is_source() && !self.is_edited(id))
+            .map(|(id, &level)| (BlockSubPos(id), level))
+    }
+
+    pub fn revision(&self) -> u64 {
+        match &self.edit {
+            Some(edit) if self.edited().next().is_some() => self.revision.max(edit.revision),
+            _ => self.revision,
+        }
     }
 
     pub fn dirty(&self) -> bool {
-        self.dirty.load(Ordering::SeqCst)
+        self.dirty_region().is_some()
+    }
+
+    /// Blocks changed since the chunk was last marked clean
+    pub fn dirty_region(&self) -> Option<DirtyRegion> {
+        let dirty = *self.dirty.lock().unwrap();
+        if self.edit_clean.load(Ordering::Relaxed) {
+            return dirty;
+        }
+        self.edited()
+            .map(|id| DirtyRegion::block(BlockSubPos(id)))
+            .fold(dirty, |dirty, region| match dirty {
+                Some(dirty) => Some(dirty.union(region)),
+                None => Some(region),
+            })
     }
 
     pub fn mark_clean(&self) {
-        self.dirty.store(false, Ordering::SeqCst);
+        *self.dirty.lock().unwrap() = None;
+        self.edit_clean.store(true, Ordering::Relaxed);
     }
 
-    pub fn empty() -> Box<Self> {
-        let mut ret = box Chunk {
-            dirty: false.into(),
-            data: [None; CHUNK_SIZE],
+    /// Chunk contains a single kind of block (or nothing)
+    pub fn is_uniform(&self) -> bool {
+        match self.edit {
+            Some(_) => {
+                let first = *self.block(0);
+                self.iter().all(|(_, &blk)| blk == first)
+            }
+            None => self.data.is_uniform(),
+        }
+    }
+
+    /// Approximate heap usage of block, light and fluid data in bytes
+    pub fn heap_size(&self) -> usize {
+        let edit = match &self.edit {
+            Some(edit) => edit.blocks.capacity() * std::mem::size_of::<Option<BlockId>>(),
+            None => 0,
         };
-        ret.mark_dirty();
-        ret
+        self.data.heap_size() + edit + self.light.heap_size() + self.fluid.heap_size()
+    }
+
+    fn from_data(data: PalettedArray) -> Box<Self> {
+        box Chunk {
+            dirty: Mutex::new(Some(DirtyRegion::all())),
+            revision: next_revision(),
+            data,
+            edit: None,
+            edit_clean: AtomicBool::new(false),
+            light: PalettedArray::filled(CHUNK_SIZE, Light::default()),
+            fluid: PalettedArray::filled(CHUNK_SIZE, FluidLevel::SOURCE),
+        }
+    }
+
+    pub fn empty() -> Box<Self> {
+        Self::from_data(PalettedArray::filled(CHUNK_SIZE, None))
+    }
+
+    pub fn filled(blk: Option<BlockId>) -> Box<Self> {
+        Self::from_data(PalettedArray::filled(CHUNK_SIZE, blk))
+    }
+
+    pub fn from_slice(blocks: &[Option<BlockId>]) -> Box<Self> {
+        assert_eq!(blocks.len(), CHUNK_SIZE, "chunk size mismatch");
+        Self::from_data(PalettedArray::from_slice(blocks))
+    }
+
+    /// Build chunk by evaluating `f` for every position in parallel
+    pub fn from_fn<F>(f: F) -> Box<Self>
+    where
+        F: Fn(BlockSubPos) -> Option<BlockId> + Send + Sync,
+    {
+        let blocks: Vec<_> = (0..CHUNK_SIZE)
+            .into_par_iter()
+            .map(|id| f(BlockSubPos(id)))
+            .collect();
+        Self::from_slice(&blocks)
     }
 }
 
 impl Index<BlockSubPos> for Chunk {
-    type Output = Option<&'static Block>;
+    type Output = Option<BlockId>;
 
     fn index(&self, index: BlockSubPos) -> &Self::Output {
-        &self.data[index.as_index()]
+        self.block(index.as_index())
     }
 }
 
 impl IndexMut<BlockSubPos> for Chunk {
     fn index_mut(&mut self, index: BlockSubPos) -> &mut Self::Output {
-        *self.dirty.get_mut() = true;
-        &mut self.data[index.as_index()]
+        &mut self.begin_edit(index.as_index(), 1)[0]
     }
 }
 
@@ -233,3 +525,106 @@ impl Add<Direction> for BlockSubPos {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::block::test_registry;
+
+    #[test]
+    fn test_index_mut() {
+        let registry = test_registry();
+        let stone = registry.lookup("stone").ok();
+        let water = registry.lookup("water").ok();
+        let pos = BlockSubPos::new(3, 4, 5);
+        let mut chunk = Chunk::filled(stone);
+        chunk.set(pos, water);
+        chunk.set_fluid(pos, FluidLevel::flowing(3));
+        chunk.mark_clean();
+        let revision = chunk.revision();
+
+        // writing the same block changes nothing
+        chunk[pos] = water;
+        assert!(!chunk.dirty());
+        assert_eq!(chunk.revision(), revision);
+        assert_eq!(chunk.fluid(pos), FluidLevel::flowing(3));
+
+        chunk[pos] = stone;
+        assert_eq!(chunk[pos], stone);
+        assert_eq!(chunk.dirty_region(), Some(DirtyRegion::block(pos)));
+        assert!(chunk.revision() > revision);
+        assert!(chunk.is_uniform());
+        chunk.mark_clean();
+        assert!(!chunk.dirty());
+
+        // stored through `set` before the next change
+        chunk.set(BlockSubPos::new(0, 0, 0), None);
+        assert_eq!(chunk[pos], stone);
+        assert_eq!(chunk.fluid(pos), FluidLevel::SOURCE);
+    }
+
+    #[test]
+    fn test_iter_mut() {
+        let registry = test_registry();
+        let stone = registry.lookup("stone").ok();
+        let mut chunk = Chunk::empty();
+        chunk.mark_clean();
+        for (pos, blk) in chunk.iter_mut() {
+            let (_, y, _) = pos.into();
+            if y < 4 {
+                *blk = stone;
+            }
+        }
+        assert_eq!(
+            chunk.iter().filter(|(_, blk)| blk.is_some()).count(),
+            4 * WIDTH * WIDTH
+        );
+        let region = chunk.dirty_region().unwrap();
+        assert_eq!(region.min, glam::ivec3(0, 0, 0));
+        assert_eq!(
+            region.max,
+            glam::ivec3(WIDTH as i32 - 1, 3, WIDTH as i32 - 1)
+        );
+
+        chunk.par_iter_mut().for_each(|(_, blk)| *blk = stone);
+        chunk.set_fluid(BlockSubPos::new(0, 0, 0), FluidLevel::SOURCE);
+        assert!(chunk.is_uniform());
+        assert_eq!(chunk.iter_solid(&registry).count(), CHUNK_SIZE);
+    }
+
+    #[test]
+    fn test_heap_size() {
+        let registry = test_registry();
+        let stone = registry.lookup("stone").ok();
+        let empty = Chunk::empty().heap_size();
+        let terrain = Chunk::from_fn(|pos| {
+            let (_, y, _) = pos.into();
+            if y < 4 {
+                stone
+            } else {
+                None
+            }
+        });
+        // two entries take a single bit per block
+        assert!(terrain.heap_size() < empty + CHUNK_SIZE / 8 + 64);
+        assert!(terrain.heap_size() < std::mem::size_of::<[Option<BlockId>; CHUNK_SIZE]>() / 8);
+
+        // light filling the chunk takes a single entry
+        let mut lit = Chunk::empty();
+        for id in 0..CHUNK_SIZE {
+            lit.set_light(BlockSubPos(id), Light::SKY);
+        }
+        assert_eq!(lit.light(BlockSubPos::new(1, 2, 3)), Light::SKY);
+        assert_eq!(lit.heap_size(), empty);
+        lit.set_light(BlockSubPos::new(1, 2, 3), Light::new(3, 7));
+        assert!(lit.heap_size() > empty);
+
+        // so does fluid once every level is a source again
+        let mut wet = Chunk::empty();
+        let pos = BlockSubPos::new(4, 5, 6);
+        wet.set_fluid(pos, FluidLevel::flowing(2));
+        assert!(wet.heap_size() > empty);
+        wet.set_fluid(pos, FluidLevel::SOURCE);
+        assert_eq!(wet.heap_size(), empty);
+    }
+}
@@ -0,0 +1,257 @@
+use std::path::Path;
+
+use anyhow::{ensure, Context};
+use noise::{MultiFractal, Seedable};
+use serde::Deserialize;
+
+use super::{
+    biome::{Biome, BiomeConfig, BiomeMap, DecorationConfig},
+    block::BlockRegistry,
+    density::{Density, DensityConfig},
+    generator::{
+        empty::EmptyGenerator,
+        flat::{FlatGenerator, Span},
+        noise::NoiseGenerator,
+        Generator,
+    },
+    structure::{StructureConfig, StructureSet, StructureTemplate},
+};
+
+/// Noise terrain parameters
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NoiseConfig {
+    /// Horizontal scale of the height noise
+    pub terrain_scale: f64,
+    pub octaves: usize,
+    /// Height difference between the lowest and highest terrain, scaled by
+    /// the biome height scale
+    pub amplitude: f32,
+    /// Terrain height where the noise is zero
+    pub sea_level: f32,
+    /// Horizontal scale of the temperature/humidity noise
+    pub biome_scale: f64,
+    /// Block below the biome subsurface layer
+    pub filler: String,
+    /// Block at the bottom of the world, `None` leaves it to the terrain
+    pub bedrock: Option<String>,
+    /// Use a 3D density function instead of plain heightmap terrain
+    pub density: Option<DensityConfig>,
+    pub biomes: Vec<BiomeConfig>,
+}
+
+fn default_biome(
+    name: &str,
+    (temperature, humidity): (f64, f64),
+    (surface, subsurface): (&str, &str),
+    height_scale: f32,
+    decoration: Option<(&str, f32, u8)>,
+) -> BiomeConfig {
+    BiomeConfig {
+        name: name.to_string(),
+        temperature,
+        humidity,
+        surface: surface.to_string(),
+        subsurface: subsurface.to_string(),
+        subsurface_depth: 3,
+        height_scale,
+        decoration: decoration.map(|(block, chance, height)| DecorationConfig {
+            block: block.to_string(),
+            chance,
+            height,
+        }),
+    }
+}
+
+impl Default for NoiseConfig {
+    fn default() -> Self {
+        Self {
+            terrain_scale: 0.03,
+            octaves: 6,
+            amplitude: 64.0,
+            sea_level: 48.0,
+            biome_scale: 0.004,
+            filler: "stone".to_string(),
+            bedrock: Some("bedrock".to_string()),
+            density: None,
+            biomes: vec![
+                default_biome("plains", (0.5, 0.4), ("grass", "dirt"), 0.4, None),
+                default_biome(
+                    "forest",
+                    (0.5, 0.8),
+                    ("grass", "dirt"),
+                    0.7,
+                    Some(("wood", 0.02, 4)),
+                ),
+                default_biome(
+                    "desert",
+                    (0.9, 0.1),
+                    ("sand", "sand"),
+                    0.3,
+                    Some(("cactus", 0.005, 3)),
+                ),
+                default_biome("mountains", (0.2, 0.3), ("stone", "stone"), 1.5, None),
+                default_biome("tundra", (0.0, 0.7), ("snow", "dirt"), 0.6, None),
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum GeneratorConfig {
+    Empty,
+    /// Layers from the bottom, block name (`None` for air) and thickness
+    Flat(Vec<(Option<String>, u8)>),
+    Noise(NoiseConfig),
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        GeneratorConfig::Noise(Default::default())
+    }
+}
+
+/// Everything needed to generate the same world again
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct WorldConfig {
+    pub seed: u32,
+    pub generator: GeneratorConfig,
+    /// Templates placed on top of the terrain, across chunk borders
+    pub structures: Vec<StructureConfig>,
+}
+
+impl WorldConfig {
+    pub fn from_ron(source: &str) -> anyhow::Result<Self> {
+        ron::from_str(source).context("invalid world config")
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read world config {}", path.display()))?;
+        Self::from_ron(&source)
+            .with_context(|| format!("failed to load world config {}", path.display()))
+    }
+
+    pub fn build_generator(&self, registry: &BlockRegistry) -> anyhow::Result<Box<dyn Generator>> {
+        Ok(match &self.generator {
+            GeneratorConfig::Empty => Box::new(EmptyGenerator),
+            GeneratorConfig::Flat(layers) => {
+                let spans = layers
+                    .iter()
+                    .map(|(name, height)| {
+                        let block = name.as_deref().map(|name| registry.lookup(name));
+                        Ok(Span(block.transpose()?, *height))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                Box::new(FlatGenerator::new(&spans))
+            }
+            GeneratorConfig::Noise(config) => {
+                let terrain = noise::HybridMulti::new()
+                    .set_seed(self.seed)
+                    .set_octaves(config.octaves);
+                let biomes = config
+                    .biomes
+                    .iter()
+                    .map(|biome| {
+                        Biome::from_config(biome, registry)
+                            .with_context(|| format!("invalid biome {:?}", biome.name))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                ensure!(!biomes.is_empty(), "no biomes defined");
+                let mut generator = NoiseGenerator::new(
+                    noise::ScalePoint::new(terrain).set_scale(config.terrain_scale),
+                    BiomeMap::new(self.seed, config.biome_scale, biomes),
+                    registry.lookup(&config.filler)?,
+                )
+                .with_level(config.sea_level, config.amplitude);
+                if let Some(bedrock) = &config.bedrock {
+                    generator = generator.with_bedrock(registry.lookup(bedrock)?);
+                }
+                if let Some(density) = &config.density {
+                    let (low, high) = density.island_range;
+                    ensure!(low < high, "empty island range {}..{}", low, high);
+                    generator = generator.with_density(Density::new(self.seed, density));
+                }
+                Box::new(generator)
+            }
+        })
+    }
+
+    pub fn build_structures(&self, registry: &BlockRegistry) -> anyhow::Result<StructureSet> {
+        let templates = self
+            .structures
+            .iter()
+            .map(|structure| {
+                StructureTemplate::from_config(structure, registry)
+                    .with_context(|| format!("invalid structure {:?}", structure.name))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        StructureSet::new(self.seed, templates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{
+        block::test_registry,
+        chunk::{BlockSubPos, Chunk},
+        storage::EncodedChunk,
+        ChunkPos,
+    };
+
+    fn generate(config: &WorldConfig, pos: ChunkPos) -> Box<Chunk> {
+        let registry = test_registry();
+        config.build_generator(&registry).unwrap().generate(pos)
+    }
+
+    #[test]
+    fn test_same_seed() {
+        let config = WorldConfig::from_ron("(seed: 1234)").unwrap();
+        let other = WorldConfig {
+            seed: 4321,
+            ..config.clone()
+        };
+        let positions = [ChunkPos(0, 1, 0), ChunkPos(-3, 2, 7), ChunkPos(12, 3, -5)];
+        let mut differs = false;
+        for &pos in &positions {
+            let a = EncodedChunk::encode(&generate(&config, pos));
+            let b = EncodedChunk::encode(&generate(&config, pos));
+            assert_eq!(a, b, "chunk {} differs", pos);
+            differs |= EncodedChunk::encode(&generate(&other, pos)) != a;
+        }
+        assert!(differs, "seed has no effect");
+    }
+
+    #[test]
+    fn test_default_config() {
+        let config = WorldConfig::from_ron(include_str!("../../assets/world.ron")).unwrap();
+        assert!(config.build_generator(&test_registry()).is_ok());
+        assert!(!config
+            .build_structures(&test_registry())
+            .unwrap()
+            .templates()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_flat() {
+        let registry = test_registry();
+        let config = WorldConfig::from_ron(
+            r#"(generator: Flat([(Some("blue"), 2), (None, 1), (Some("red"), 1)]))"#,
+        )
+        .unwrap();
+        let chunk = config
+            .build_generator(&registry)
+            .unwrap()
+            .generate(ChunkPos(0, 0, 0));
+        let blocks: Vec<_> = (0..4).map(|y| chunk[BlockSubPos::new(0, y, 0)]).collect();
+        let block = |name| registry.lookup(name).ok();
+        assert_eq!(blocks, [block("blue"), block("blue"), None, block("red")]);
+
+        let config = WorldConfig::from_ron(r#"(generator: Flat([(Some("missing"), 1)]))"#).unwrap();
+        assert!(config.build_generator(&registry).is_err());
+    }
+}
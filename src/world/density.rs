@@ -0,0 +1,89 @@
+use noise::{NoiseFn, Seedable};
+use serde::Deserialize;
+
+/// 3D terrain parameters, noise values are in `-1.0..=1.0`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DensityConfig {
+    pub overhang_scale: f64,
+    /// Maximum distance (in blocks) the surface is pushed away from the height
+    /// level, large values relative to `overhang_scale` give overhangs
+    pub overhang_strength: f32,
+    pub cave_scale: f64,
+    /// Tunnels are carved where both cave noises are closer to zero than this
+    pub cave_threshold: f32,
+    pub island_scale: f64,
+    /// Island noise needed for a solid block in the middle of the island band
+    pub island_threshold: f32,
+    /// Lowest and highest y of floating islands
+    pub island_range: (f32, f32),
+}
+
+impl Default for DensityConfig {
+    fn default() -> Self {
+        Self {
+            overhang_scale: 0.04,
+            overhang_strength: 8.0,
+            cave_scale: 0.04,
+            cave_threshold: 0.07,
+            island_scale: 0.02,
+            island_threshold: 0.3,
+            island_range: (150.0, 200.0),
+        }
+    }
+}
+
+/// Density function combining the 2D height level with 3D noise
+pub struct Density {
+    config: DensityConfig,
+    overhang: noise::OpenSimplex,
+    caves: [noise::OpenSimplex; 2],
+    islands: noise::OpenSimplex,
+}
+
+impl Density {
+    pub fn new(seed: u32, config: &DensityConfig) -> Self {
+        let noise = |offset| noise::OpenSimplex::new().set_seed(seed.wrapping_add(offset));
+        Self {
+            config: config.clone(),
+            overhang: noise(5),
+            caves: [noise(6), noise(7)],
+            islands: noise(8),
+        }
+    }
+
+    /// Maximum distance between the surface and the height level
+    pub fn overhang_strength(&self) -> f32 {
+        self.config.overhang_strength
+    }
+
+    fn sample(noise: &noise::OpenSimplex, scale: f64, x: i32, y: i32, z: i32) -> f32 {
+        noise.get([x as f64 * scale, y as f64 * scale, z as f64 * scale]) as f32
+    }
+
+    /// Whether the block belongs to the terrain or an island, caves not
+    /// included
+    pub fn is_solid(&self, x: i32, y: i32, z: i32, level: f32) -> bool {
+        let config = &self.config;
+        let overhang = Self::sample(&self.overhang, config.overhang_scale, x, y, z);
+        if level - y as f32 + overhang * config.overhang_strength > 0.0 {
+            return true;
+        }
+        let (low, high) = config.island_range;
+        if (y as f32) <= low || (y as f32) >= high {
+            return false;
+        }
+        // islands thin out towards both ends of the band
+        let half = (high - low) * 0.5;
+        let falloff = 1.0 - ((y as f32 - low - half) / half).powi(2);
+        let island = Self::sample(&self.islands, config.island_scale, x, y, z);
+        island * falloff > config.island_threshold
+    }
+
+    pub fn is_cave(&self, x: i32, y: i32, z: i32) -> bool {
+        let config = &self.config;
+        self.caves.iter().all(|noise| {
+            Self::sample(noise, config.cave_scale, x, y, z).abs() < config.cave_threshold
+        })
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::BTreeSet;
+
+use glam::IVec3;
+use strum::IntoEnumIterator;
+
+use crate::common::direction::Direction;
+
+use super::{
+    block::{BlockId, BlockRegistry, BlockType},
+    convert_pos, ChunkNeighbor, Map,
+};
+
+/// Flag bit of fluid pouring down from the block above
+pub const FALLING: u8 = 0x80;
+
+/// Fluid state of a block, only meaningful for fluid blocks
+///
+/// Source blocks are `0`, flowing fluid keeps its distance to the source.
+/// Falling fluid acts like a source for the blocks it lands on.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FluidLevel(u8);
+
+impl FluidLevel {
+    pub const SOURCE: Self = Self(0);
+    pub const FALLING: Self = Self(FALLING);
+
+    pub fn flowing(distance: u8) -> Self {
+        Self(distance.min(FALLING - 1))
+    }
+
+    pub fn from_raw(raw: u8) -> Self {
+        Self(raw)
+    }
+
+    pub fn raw(self) -> u8 {
+        self.0
+    }
+
+    pub fn is_source(self) -> bool {
+        self == Self::SOURCE
+    }
+
+    pub fn is_falling(self) -> bool {
+        self.0 & FALLING != 0
+    }
+
+    /// Blocks travelled sideways, `0` for sources and falling fluid
+    pub fn distance(self) -> u8 {
+        if self.is_falling() {
+            0
+        } else {
+            self.0
+        }
+    }
+
+    /// Surface height inside the block, `0.0..=1.0`
+    pub fn height(self, spread: u8) -> f32 {
+        let spread = spread as f32 + 1.0;
+        (spread - self.distance() as f32).max(1.0) / spread
+    }
+}
+
+pub(super) fn offset(direction: Direction) -> IVec3 {
+    let (x, y, z) = ChunkNeighbor::from(direction).as_offsets();
+    IVec3::new(x, y, z)
+}
+
+fn spread(registry: &BlockRegistry, block: BlockId) -> Option<u8> {
+    match registry[block].data {
+        BlockType::Fluid { spread, .. } => Some(spread),
+        _ => None,
+    }
+}
+
+/// Cellular fluid flow
+///
+/// Only blocks around ticked fluid are looked at, every step moves fluid by
+/// one block: down first, sideways with the distance to the source growing
+/// until it reaches the `spread` of the fluid. Flowing fluid cut off from its
+/// source dries up the same way.
+impl Map {
+    /// Fluid block and its level, `None` for other blocks and unloaded chunks
+    pub fn fluid_at(&self, registry: &BlockRegistry, pos: IVec3) -> Option<(BlockId, FluidLevel)> {
+        let (chunk_pos, sub_pos) = convert_pos(pos)?;
+        let chunk = self.get(chunk_pos)?;
+        let block = chunk[sub_pos]?;
+        spread(registry, block)?;
+        Some((block, chunk.fluid(sub_pos)))
+    }
+
+    /// Replace the block and its fluid level, `None` clears the block
+    fn set_fluid(&mut self, pos: IVec3, fluid: Option<(BlockId, FluidLevel)>) {
+        let block = fluid.map(|(block, _)| block);
+        if self.block_at(pos) != Some(block) {
+            self.set_block(pos, block);
+        }
+        if let (Some((chunk_pos, sub_pos)), Some((_, level))) = (convert_pos(pos), fluid) {
+            if let Some(chunk) = self.get_mut(chunk_pos) {
+                chunk.set_fluid(sub_pos, level);
+            }
+        }
+    }
+
+    /// Fluid the block should hold, the outer `None` leaves it untouched
+    fn expected_fluid(
+        &self,
+        registry: &BlockRegistry,
+        pos: IVec3,
+    ) -> Option<Option<(BlockId, FluidLevel)>> {
+        let current = self.block_at(pos)?;
+        if current.is_some() {
+            // sources stay, other blocks are never washed away
+            match self.fluid_at(registry, pos) {
+                Some((_, level)) if !level.is_source() => {}
+                _ => return None,
+            }
+        }
+        // fluid of another kind doesn't mix in
+        let accepts = |block: BlockId| match current {
+            Some(current) => current == block,
+            None => true,
+        };
+
+        if let Some((above, _)) = self.fluid_at(registry, pos + IVec3::Y) {
+            if accepts(above) {
+                return Some(Some((above, FluidLevel::FALLING)));
+            }
+        }
+
+        let mut best: Option<(BlockId, u8)> = None;
+        for direction in Direction::iter() {
+            if matches!(direction, Direction::Up | Direction::Down) {
+                continue;
+            }
+            let next = pos + offset(direction);
+            let (block, level) = match self.fluid_at(registry, next) {
+                Some(fluid) if accepts(fluid.0) => fluid,
+                _ => continue,
+            };
+            // fluid that can flow down doesn't spread
+            let below = next - IVec3::Y;
+            let pours = match self.block_at(below) {
+                Some(None) => true,
+                Some(Some(_)) => match self.fluid_at(registry, below) {
+                    Some((_, level)) => !level.is_source(),
+                    None => false,
+                },
+                None => false,
+            };
+            if pours {
+                continue;
+            }
+            let distance = level.distance() + 1;
+            let closer = match best {
+                Some((_, best)) => distance < best,
+                None => true,
+            };
+            if distance <= spread(registry, block)? && closer {
+                best = Some((block, distance));
+            }
+        }
+        Some(best.map(|(block, distance)| (block, FluidLevel::flowing(distance))))
+    }
+
+    /// Advance fluid around the ticked blocks by one step, returns the
+    /// changed blocks
+    ///
+    /// All blocks are evaluated against the state before the step, so the
+    /// result doesn't depend on the order of `ticked`.
+    pub fn step_fluids(&mut self, registry: &BlockRegistry, ticked: &[IVec3]) -> Vec<IVec3> {
+        let pending: BTreeSet<(i32, i32, i32)> = ticked
+            .iter()
+            .flat_map(|&pos| {
+                std::iter::once(pos).chain(Direction::iter().map(move |dir| pos + offset(dir)))
+            })
+            .map(|pos| (pos.x, pos.y, pos.z))
+            .collect();
+        let updates: Vec<_> = pending
+            .into_iter()
+            .map(|(x, y, z)| IVec3::new(x, y, z))
+            .filter_map(|pos| {
+                let expected = self.expected_fluid(registry, pos)?;
+                let current = match self.block_at(pos)? {
+                    Some(_) => self.fluid_at(registry, pos),
+                    None => None,
+                };
+                if current == expected {
+                    None
+                } else {
+                    Some((pos, expected))
+                }
+            })
+            .collect();
+        for &(pos, fluid) in &updates {
+            self.set_fluid(pos, fluid);
+        }
+        updates.into_iter().map(|(pos, _)| pos).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{
+        block::test_registry,
+        generator::flat::{FlatGenerator, Span},
+        ChunkPos,
+    };
+
+    /// Step until nothing changes, ticking every changed block again
+    fn run(map: &mut Map, registry: &BlockRegistry, pos: IVec3) {
+        let mut ticked = vec![pos];
+        for _ in 0..64 {
+            ticked = map.step_fluids(registry, &ticked);
+            if ticked.is_empty() {
+                return;
+            }
+        }
+        panic!("fluid didn't settle");
+    }
+
+    #[test]
+    fn test_level() {
+        assert_eq!(FluidLevel::SOURCE.height(7), 1.0);
+        assert_eq!(FluidLevel::FALLING.height(7), 1.0);
+        assert_eq!(FluidLevel::flowing(4).height(7), 0.5);
+        assert!(FluidLevel::flowing(7).height(7) > 0.0);
+    }
+
+    #[test]
+    fn test_flow() {
+        let registry = test_registry();
+        let ground = Span(registry.lookup("stone").ok(), 4);
+        let mut map = Map::new(FlatGenerator::new(&[ground]));
+        for pos in ChunkPos(0, 0, 0).iter_range(1) {
+            map.load_chunk(pos);
+        }
+        let water = registry.lookup("water").unwrap();
+        // source two blocks above the ground pours down, then spreads
+        let source = IVec3::new(8, 6, 8);
+        map.set_fluid(source, Some((water, FluidLevel::SOURCE)));
+        run(&mut map, &registry, source);
+
+        let level = |pos: IVec3| map.fluid_at(&registry, pos).map(|(_, level)| level);
+        assert_eq!(level(IVec3::new(8, 5, 8)), Some(FluidLevel::FALLING));
+        assert_eq!(level(IVec3::new(8, 4, 8)), Some(FluidLevel::FALLING));
+        assert_eq!(level(IVec3::new(9, 6, 8)), None);
+        assert_eq!(level(IVec3::new(9, 5, 8)), None);
+        assert_eq!(level(IVec3::new(11, 4, 8)), Some(FluidLevel::flowing(3)));
+        assert_eq!(level(IVec3::new(10, 4, 10)), Some(FluidLevel::flowing(4)));
+        assert_eq!(level(IVec3::new(15, 4, 8)), Some(FluidLevel::flowing(7)));
+        assert_eq!(level(IVec3::new(16, 4, 8)), None);
+
+        // without its source everything dries up
+        map.set_fluid(source, None);
+        run(&mut map, &registry, source);
+        for (_, chunk) in map.iter() {
+            assert!(chunk.iter().all(|(_, &blk)| blk != Some(water)));
+        }
+    }
+}
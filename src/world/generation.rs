@@ -0,0 +1,137 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    sync::Arc,
+};
+
+use crossbeam_channel::{Receiver, Sender};
+
+use super::{
+    chunk::Chunk,
+    generator::Generator,
+    structure::{Placement, StructureSet},
+    ChunkPos,
+};
+
+/// Generated chunk and the structure rooted in it
+pub type Generated = (Box<Chunk>, Option<Placement>);
+
+/// Lifecycle of a chunk slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChunkState {
+    /// Waiting in the queue
+    Pending,
+    /// Handed to a worker thread
+    Generating,
+    /// Chunk data is available
+    Ready,
+}
+
+/// Runs [`Generator::generate`] on the rayon pool, along with the search for
+/// a structure rooted in the chunk
+///
+/// Requests are dispatched in order, with a bounded number of jobs in flight,
+/// so chunks requested first (nearest to the player) are generated first.
+pub struct GenerationQueue {
+    generator: Arc<dyn Generator>,
+    structures: Arc<StructureSet>,
+    states: HashMap<ChunkPos, ChunkState>,
+    pending: VecDeque<ChunkPos>,
+    in_flight: usize,
+    sender: Sender<(ChunkPos, Generated)>,
+    receiver: Receiver<(ChunkPos, Generated)>,
+}
+
+impl GenerationQueue {
+    pub fn new(generator: Arc<dyn Generator>) -> Self {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        Self {
+            generator,
+            structures: Default::default(),
+            states: Default::default(),
+            pending: Default::default(),
+            in_flight: 0,
+            sender,
+            receiver,
+        }
+    }
+
+    pub fn generator(&self) -> &dyn Generator {
+        self.generator.as_ref()
+    }
+
+    pub fn structures(&self) -> &StructureSet {
+        &self.structures
+    }
+
+    /// Structures are searched in chunks dispatched from now on
+    pub fn set_structures(&mut self, structures: StructureSet) {
+        self.structures = Arc::new(structures);
+    }
+
+    /// Generate a chunk on the current thread
+    pub fn generate(&self, pos: ChunkPos) -> Generated {
+        generate(self.generator.as_ref(), &self.structures, pos)
+    }
+
+    pub fn state(&self, pos: ChunkPos) -> Option<ChunkState> {
+        self.states.get(&pos).copied()
+    }
+
+    pub fn requested(&self) -> impl '_ + Iterator<Item = ChunkPos> {
+        self.states.keys().copied()
+    }
+
+    pub fn request(&mut self, pos: ChunkPos) {
+        if let Entry::Vacant(entry) = self.states.entry(pos) {
+            entry.insert(ChunkState::Pending);
+            self.pending.push_back(pos);
+        }
+    }
+
+    /// Forget the request, a running job is discarded when it finishes
+    pub fn cancel(&mut self, pos: ChunkPos) -> bool {
+        self.states.remove(&pos).is_some()
+    }
+
+    fn dispatch(&mut self) {
+        let limit = rayon::current_num_threads() * 2;
+        while self.in_flight < limit {
+            let pos = match self.pending.pop_front() {
+                Some(pos) => pos,
+                None => break,
+            };
+            match self.states.get_mut(&pos) {
+                Some(state @ ChunkState::Pending) => *state = ChunkState::Generating,
+                // cancelled while waiting
+                _ => continue,
+            }
+            self.in_flight += 1;
+            let generator = self.generator.clone();
+            let structures = self.structures.clone();
+            let sender = self.sender.clone();
+            rayon::spawn(move || {
+                let generated = generate(generator.as_ref(), &structures, pos);
+                // receiver lives as long as the queue, nothing to do if it's gone
+                let _ = sender.send((pos, generated));
+            });
+        }
+    }
+
+    /// Start queued jobs and collect finished chunks
+    pub fn poll(&mut self) -> Vec<(ChunkPos, Generated)> {
+        self.dispatch();
+        let mut finished = Vec::new();
+        for (pos, generated) in self.receiver.try_iter() {
+            self.in_flight -= 1;
+            if self.states.get(&pos) == Some(&ChunkState::Generating) {
+                self.states.remove(&pos);
+                finished.push((pos, generated));
+            }
+        }
+        finished
+    }
+}
+
+fn generate(generator: &dyn Generator, structures: &StructureSet, pos: ChunkPos) -> Generated {
+    (generator.generate(pos), structures.rooted(pos, generator))
+}
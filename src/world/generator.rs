@@ -1,10 +1,32 @@
-use super::{chunk::Chunk, ChunkPos, SizeTuple};
+use super::{biome::Biome, chunk::Chunk, ChunkPos};
 
-use rayon::prelude::*;
-
-pub trait Generator {
-    fn init(&mut self, size: SizeTuple);
+pub trait Generator: Send + Sync {
     fn generate(&self, pos: ChunkPos) -> Box<Chunk>;
+
+    /// Biome of the column, generators without biomes return `None`
+    fn biome_at(&self, _x: i32, _z: i32) -> Option<&Biome> {
+        None
+    }
+
+    /// First air block above the terrain of the column, used to place
+    /// structures, `None` if the column has no usable surface
+    fn surface_height(&self, _x: i32, _z: i32) -> Option<i32> {
+        None
+    }
+}
+
+impl<G: Generator + ?Sized> Generator for Box<G> {
+    fn generate(&self, pos: ChunkPos) -> Box<Chunk> {
+        self.as_ref().generate(pos)
+    }
+
+    fn biome_at(&self, x: i32, z: i32) -> Option<&Biome> {
+        self.as_ref().biome_at(x, z)
+    }
+
+    fn surface_height(&self, x: i32, z: i32) -> Option<i32> {
+        self.as_ref().surface_height(x, z)
+    }
 }
 
 pub mod empty {
@@ -13,8 +35,6 @@ pub mod empty {
     pub struct EmptyGenerator;
 
     impl Generator for EmptyGenerator {
-        fn init(&mut self, _size: SizeTuple) {}
-
         fn generate(&self, _pos: ChunkPos) -> Box<Chunk> {
             Chunk::empty()
         }
@@ -22,15 +42,17 @@ pub mod empty {
 }
 
 pub mod flat {
-    use crate::world::block::Block;
+    use std::convert::TryFrom;
+
+    use crate::world::block::BlockId;
 
     use super::*;
 
     #[derive(Debug, Clone, Copy)]
-    pub struct Span(pub Option<&'static Block>, pub u8);
+    pub struct Span(pub Option<BlockId>, pub u8);
 
     pub struct FlatGenerator {
-        data: Vec<Option<&'static Block>>,
+        data: Vec<Option<BlockId>>,
     }
 
     impl FlatGenerator {
@@ -46,102 +68,295 @@ pub mod flat {
             Self { data: arr }
         }
 
-        fn fetch_block(&self, pos: u8) -> Option<&'static Block> {
-            let pos = pos as usize;
-            if pos < self.data.len() {
-                self.data[pos]
-            } else {
-                None
+        fn fetch_block(&self, pos: i32) -> Option<BlockId> {
+            match usize::try_from(pos) {
+                Ok(pos) if pos < self.data.len() => self.data[pos],
+                _ => None,
             }
         }
     }
 
     impl Generator for FlatGenerator {
-        fn init(&mut self, _size: SizeTuple) {}
-
-        fn generate(&self, _pos: ChunkPos) -> Box<Chunk> {
-            let mut ret = Chunk::empty();
-            ret.par_iter_mut().for_each(|(pos, blk)| {
+        fn generate(&self, chunk_pos: ChunkPos) -> Box<Chunk> {
+            let base = chunk_pos.1 * Chunk::HEIGHT as i32;
+            Chunk::from_fn(|pos| {
                 let (_, y, _) = pos.into();
-                if let Some(new) = self.fetch_block(y) {
-                    blk.replace(new);
-                }
-            });
-            ret
+                self.fetch_block(base + y as i32)
+            })
+        }
+
+        fn surface_height(&self, _x: i32, _z: i32) -> Option<i32> {
+            let top = self.data.iter().rposition(|block| block.is_some())?;
+            Some(top as i32 + 1)
         }
     }
 }
 
 pub mod noise {
-    use rayon::iter::ParallelIterator;
-
-    use crate::world::{block::Block, chunk::Chunk, ChunkPos};
+    use crate::world::{
+        biome::{Biome, BiomeMap},
+        block::BlockId,
+        chunk::{BlockSubPos, Chunk},
+        density::Density,
+        ChunkPos, WORLD_HEIGHT,
+    };
 
     use super::Generator;
 
-    pub struct NoiseGenerator<F, F2>
+    /// Terrain below a noise height level, surface blocks and height scale
+    /// come from biomes
+    ///
+    /// With a [`Density`] the height level only biases a 3D density function,
+    /// which adds caves, overhangs and floating islands.
+    pub struct NoiseGenerator<F>
     where
         F: noise::NoiseFn<[f64; 3]> + Send + Sync,
-        F2: noise::NoiseFn<[f64; 3]> + Send + Sync,
     {
         noise_fn: F,
-        color_noise_fn: F2,
+        biomes: BiomeMap,
+        filler: BlockId,
+        bedrock: Option<BlockId>,
+        density: Option<Density>,
+        base_level: f32,
+        amplitude: f32,
+    }
+
+    struct Column<'a> {
+        x: i32,
+        z: i32,
+        /// Terrain height from the 2D noise
+        level: f32,
+        biome: &'a Biome,
+        /// Decoration block and the y range it occupies
+        decoration: Option<(BlockId, i32, i32)>,
     }
 
-    impl<F, F2> NoiseGenerator<F, F2>
+    impl<F> NoiseGenerator<F>
     where
         F: noise::NoiseFn<[f64; 3]> + Send + Sync,
-        F2: noise::NoiseFn<[f64; 3]> + Send + Sync,
     {
-        pub fn new(noise_fn: F, color_noise_fn: F2) -> Self {
+        /// `filler` is used below the subsurface layer of every biome
+        pub fn new(noise_fn: F, biomes: BiomeMap, filler: BlockId) -> Self {
             Self {
                 noise_fn,
-                color_noise_fn,
+                biomes,
+                filler,
+                bedrock: None,
+                density: None,
+                base_level: 32.0,
+                amplitude: 64.0,
             }
         }
 
-        fn get_level(&self, pos: [f64; 3]) -> f32 {
-            self.noise_fn.get(pos) as f32 * 64.0 + 32.0
+        /// Terrain height is `base_level + noise * amplitude * height_scale`
+        pub fn with_level(mut self, base_level: f32, amplitude: f32) -> Self {
+            self.base_level = base_level;
+            self.amplitude = amplitude;
+            self
         }
 
-        fn get_color_level(&self, pos: [f64; 3]) -> &'static Block {
-            use crate::world::block::constants::*;
-            let selection = [
-                GREEN_BLOCK,
-                RED_BLOCK,
-                BLUE_BLOCK,
-                PURPLE_BLOCK,
-                YELLOW_BLOCK,
-                AQUA_BLOCK,
-            ];
-            let raw = self.color_noise_fn.get(pos);
-            let idx = (raw * 6.0 + 3.0).clamp(0.0, 5.9).floor() as usize;
-            selection[idx]
+        /// Block placed at y = 0 regardless of terrain and caves
+        pub fn with_bedrock(mut self, bedrock: BlockId) -> Self {
+            self.bedrock = Some(bedrock);
+            self
+        }
+
+        pub fn with_density(mut self, density: Density) -> Self {
+            self.density = Some(density);
+            self
+        }
+
+        fn get_column(&self, x: i32, z: i32) -> Column<'_> {
+            let sample = self.biomes.sample(x, z);
+            let noise = self.noise_fn.get([z as f64, 0.0, x as f64]) as f32;
+            let level = noise * self.amplitude * sample.height_scale + self.base_level;
+            let mut column = Column {
+                x,
+                z,
+                level,
+                biome: sample.biome,
+                decoration: None,
+            };
+            let top = level.ceil() as i32;
+            let exposed = match &self.density {
+                Some(density) => {
+                    self.is_solid(&column, top - 1)
+                        && !self.is_solid(&column, top)
+                        && !density.is_cave(x, top - 1, z)
+                }
+                None => true,
+            };
+            if exposed {
+                column.decoration = self
+                    .biomes
+                    .decoration(sample.biome, x, z)
+                    .map(|decoration| (decoration.block, top, top + decoration.height as i32));
+            }
+            column
+        }
+
+        /// Terrain without caves
+        fn is_solid(&self, column: &Column, y: i32) -> bool {
+            match &self.density {
+                Some(density) => density.is_solid(column.x, y, column.z, column.level),
+                None => (y as f32) < column.level,
+            }
+        }
+
+        /// Blocks of one column of the section starting at `y0`
+        fn column_blocks(&self, column: &Column, y0: i32) -> Vec<Option<BlockId>> {
+            let biome = column.biome;
+            let layers = 1 + biome.subsurface_depth as usize;
+            // a few extra blocks above the section to find the surface
+            let solid: Vec<bool> = (y0..y0 + (Chunk::HEIGHT + layers) as i32)
+                .map(|y| self.is_solid(column, y))
+                .collect();
+            (0..Chunk::HEIGHT)
+                .map(|i| {
+                    let y = y0 + i as i32;
+                    if y == 0 && self.bedrock.is_some() {
+                        return self.bedrock;
+                    }
+                    if y > (WORLD_HEIGHT - 4) as i32 {
+                        return None;
+                    }
+                    if !solid[i] {
+                        return match column.decoration {
+                            Some((block, start, end)) if y >= start && y < end => Some(block),
+                            _ => None,
+                        };
+                    }
+                    if let Some(density) = &self.density {
+                        if density.is_cave(column.x, y, column.z) {
+                            return None;
+                        }
+                    }
+                    let depth = solid[i + 1..i + 1 + layers]
+                        .iter()
+                        .take_while(|&&solid| solid)
+                        .count();
+                    Some(if depth == 0 {
+                        biome.surface
+                    } else if depth < layers {
+                        biome.subsurface
+                    } else {
+                        self.filler
+                    })
+                })
+                .collect()
         }
     }
 
-    impl<F, F2> Generator for NoiseGenerator<F, F2>
+    impl<F> Generator for NoiseGenerator<F>
     where
         F: noise::NoiseFn<[f64; 3]> + Send + Sync,
-        F2: noise::NoiseFn<[f64; 3]> + Send + Sync,
     {
-        fn init(&mut self, _size: crate::world::SizeTuple) {}
-
         fn generate(&self, chunk_pos: ChunkPos) -> Box<Chunk> {
-            let mut ret = Chunk::empty();
-            ret.par_iter_mut().for_each(|(block_pos, blk)| {
-                let pos = chunk_pos + block_pos;
-                // log::info!("{}+{} = {}", chunk_pos, block_pos, pos);
-                if pos.y > (Chunk::HEIGHT - 4) as f32 {
-                    return;
+            let origin = chunk_pos + BlockSubPos::new(0, 0, 0);
+            let (x0, y0, z0) = (origin.x as i32, origin.y as i32, origin.z as i32);
+            let width = Chunk::WIDTH as i32;
+            let columns: Vec<Vec<Option<BlockId>>> = (0..width * width)
+                .map(|i| {
+                    let column = self.get_column(x0 + i % width, z0 + i / width);
+                    self.column_blocks(&column, y0)
+                })
+                .collect();
+            Chunk::from_fn(|block_pos| {
+                let (x, y, z) = block_pos.into();
+                columns[x as usize + z as usize * Chunk::WIDTH][y as usize]
+            })
+        }
+
+        fn biome_at(&self, x: i32, z: i32) -> Option<&Biome> {
+            Some(self.biomes.biome_at(x, z))
+        }
+
+        fn surface_height(&self, x: i32, z: i32) -> Option<i32> {
+            let column = self.get_column(x, z);
+            let top = column.level.ceil() as i32;
+            let y = match &self.density {
+                // highest solid block within the overhang range, skipping
+                // floating islands above it
+                Some(density) => {
+                    let reach = density.overhang_strength().ceil() as i32;
+                    let mut above = self.is_solid(&column, top + reach);
+                    let mut surface = None;
+                    for y in (top - reach..top + reach).rev() {
+                        let solid = self.is_solid(&column, y);
+                        if solid && !above {
+                            if !density.is_cave(x, y, z) {
+                                surface = Some(y + 1);
+                            }
+                            break;
+                        }
+                        above = solid;
+                    }
+                    surface?
                 }
-                let v = pos.y;
-                let level = self.get_level([pos.z as f64, pos.y as f64, pos.x as f64]);
-                if level > v {
-                    blk.replace(self.get_color_level([pos.x as f64, pos.y as f64, pos.z as f64]));
+                None => top,
+            };
+            if y > 0 && y < (WORLD_HEIGHT - 4) as i32 {
+                Some(y)
+            } else {
+                None
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use crate::world::{
+            block::test_registry, chunk::BlockSubPos, config::WorldConfig, generator::Generator,
+            ChunkPos,
+        };
+
+        fn generator(source: &str) -> Box<dyn Generator> {
+            let config = WorldConfig::from_ron(source).unwrap();
+            config.build_generator(&test_registry()).unwrap()
+        }
+
+        #[test]
+        fn test_caves() {
+            let plain = generator("(seed: 99)");
+            // no overhangs, so only caves differ from the heightmap terrain
+            let caves = generator(
+                "(seed: 99, generator: Noise((density: Some((overhang_strength: 0.0)))))",
+            );
+            let mut carved = 0;
+            for x in -2..2 {
+                for z in -2..2 {
+                    for y in 0..3 {
+                        let pos = ChunkPos(x, y, z);
+                        let (a, b) = (plain.generate(pos), caves.generate(pos));
+                        carved += a
+                            .iter()
+                            .filter(|&(pos, block)| block.is_some() && b[pos].is_none())
+                            .count();
+                    }
+                }
+            }
+            assert!(carved > 1000, "only {} blocks carved", carved);
+        }
+
+        #[test]
+        fn test_bedrock() {
+            let registry = test_registry();
+            let bedrock = registry.lookup("bedrock").ok();
+            for source in &[
+                "(seed: 5)",
+                "(seed: 5, generator: Noise((density: Some(()))))",
+            ] {
+                let generator = generator(source);
+                for x in -3..3 {
+                    for z in -3..3 {
+                        let chunk = generator.generate(ChunkPos(x, 0, z));
+                        for (sub_x, sub_z) in (0..16).flat_map(|x| (0..16).map(move |z| (x, z))) {
+                            let block = chunk[BlockSubPos::new(sub_x, 0, sub_z)];
+                            assert_eq!(block, bedrock, "hole in chunk {} {}", x, z);
+                        }
+                    }
                 }
-            });
-            ret
+            }
         }
     }
 }
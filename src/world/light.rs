@@ -0,0 +1,395 @@
+use std::collections::VecDeque;
+
+use glam::IVec3;
+use strum::IntoEnumIterator;
+
+use crate::common::direction::Direction;
+
+use super::{
+    block::{BlockId, BlockRegistry},
+    chunk::Chunk,
+    convert_pos, ChunkNeighbor, ChunkPos, Map,
+};
+
+pub const MAX_LIGHT: u8 = 15;
+
+/// Skylight and block light of a block, 4 bits each
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Light(u8);
+
+impl Light {
+    /// Full skylight, used where lighting is unknown
+    pub const SKY: Self = Self(MAX_LIGHT << 4);
+
+    pub fn new(sky: u8, block: u8) -> Self {
+        Self(sky.min(MAX_LIGHT) << 4 | block.min(MAX_LIGHT))
+    }
+
+    pub fn sky(self) -> u8 {
+        self.0 >> 4
+    }
+
+    pub fn block(self) -> u8 {
+        self.0 & MAX_LIGHT
+    }
+
+    fn get(self, channel: Channel) -> u8 {
+        match channel {
+            Channel::Sky => self.sky(),
+            Channel::Block => self.block(),
+        }
+    }
+
+    fn with(self, channel: Channel, level: u8) -> Self {
+        match channel {
+            Channel::Sky => Self::new(level, self.block()),
+            Channel::Block => Self::new(self.sky(), level),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Channel {
+    Sky,
+    Block,
+}
+
+const CHANNELS: [Channel; 2] = [Channel::Sky, Channel::Block];
+
+type LightQueue = VecDeque<(IVec3, u8)>;
+
+fn is_transparent(registry: &BlockRegistry, block: Option<BlockId>) -> bool {
+    match block {
+        Some(block) => !registry[block].data.is_opaque(),
+        None => true,
+    }
+}
+
+fn emission(registry: &BlockRegistry, block: Option<BlockId>) -> u8 {
+    match block {
+        Some(block) => registry[block].data.emission().min(MAX_LIGHT),
+        None => 0,
+    }
+}
+
+fn offset(direction: Direction) -> IVec3 {
+    let (x, y, z) = ChunkNeighbor::from(direction).as_offsets();
+    IVec3::new(x, y, z)
+}
+
+/// Level reached by stepping from a block with `level` towards `direction`,
+/// full skylight goes straight down without falling off
+fn spread(channel: Channel, level: u8, direction: Direction) -> u8 {
+    match (channel, direction) {
+        (Channel::Sky, Direction::Down) if level == MAX_LIGHT => MAX_LIGHT,
+        _ => level.saturating_sub(1),
+    }
+}
+
+/// Flood-fill lighting over loaded chunks
+///
+/// Chunks without a loaded section above them are lit as if open to the sky,
+/// the section below is corrected once the one above shows up. Light is not
+/// saved, [`Map::light_chunk`] has to run for every chunk that becomes ready.
+impl Map {
+    /// `None` when the chunk isn't loaded
+    pub fn light_at(&self, pos: IVec3) -> Option<Light> {
+        let (chunk_pos, sub_pos) = convert_pos(pos)?;
+        Some(self.get(chunk_pos)?.light(sub_pos))
+    }
+
+    fn set_light(&mut self, pos: IVec3, light: Light) {
+        if let Some((chunk_pos, sub_pos)) = convert_pos(pos) {
+            if let Some(chunk) = self.get_mut(chunk_pos) {
+                chunk.set_light(sub_pos, light);
+            }
+        }
+    }
+
+    fn set_level(&mut self, pos: IVec3, channel: Channel, level: u8) {
+        if let Some(light) = self.light_at(pos) {
+            self.set_light(pos, light.with(channel, level));
+        }
+    }
+
+    /// Nothing loaded above, the block sees the sky
+    fn is_open_sky(&self, pos: IVec3) -> bool {
+        match convert_pos(pos + IVec3::Y) {
+            Some((chunk_pos, _)) => !self.is_loaded(chunk_pos),
+            None => true,
+        }
+    }
+
+    fn spread_light(&mut self, registry: &BlockRegistry, channel: Channel, mut queue: LightQueue) {
+        while let Some((pos, level)) = queue.pop_front() {
+            // a brighter value may have replaced the queued one
+            match self.light_at(pos) {
+                Some(light) if light.get(channel) == level => {}
+                _ => continue,
+            }
+            for direction in Direction::iter() {
+                let next = pos + offset(direction);
+                let level = spread(channel, level, direction);
+                if level == 0 {
+                    continue;
+                }
+                match (self.block_at(next), self.light_at(next)) {
+                    (Some(block), Some(light))
+                        if is_transparent(registry, block) && light.get(channel) < level =>
+                    {
+                        self.set_light(next, light.with(channel, level));
+                        queue.push_back((next, level));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Darken everything lit by the seeds, which are already set to zero
+    /// and carry their previous level. Returns the blocks that have to
+    /// spread light again.
+    fn remove_light(
+        &mut self,
+        registry: &BlockRegistry,
+        channel: Channel,
+        mut queue: LightQueue,
+    ) -> LightQueue {
+        let mut relight = LightQueue::new();
+        while let Some((pos, level)) = queue.pop_front() {
+            for direction in Direction::iter() {
+                let next = pos + offset(direction);
+                let current = match self.light_at(next) {
+                    Some(light) => light.get(channel),
+                    None => continue,
+                };
+                if current == 0 {
+                    continue;
+                }
+                if current <= spread(channel, level, direction) {
+                    self.set_level(next, channel, 0);
+                    queue.push_back((next, current));
+                    if channel == Channel::Block {
+                        let emission = self.block_at(next).map(|block| emission(registry, block));
+                        if let Some(emission @ 1..=MAX_LIGHT) = emission {
+                            self.set_level(next, channel, emission);
+                            relight.push_back((next, emission));
+                        }
+                    }
+                } else {
+                    relight.push_back((next, current));
+                }
+            }
+        }
+        relight
+    }
+
+    /// Compute light of a chunk that just became ready and spread it into
+    /// loaded neighbors
+    pub fn light_chunk(&mut self, pos: ChunkPos, registry: &BlockRegistry) {
+        let origin = pos.origin();
+        let blocks: Vec<_> = match self.get_mut(pos) {
+            Some(chunk) => {
+                chunk.clear_light();
+                chunk
+                    .iter()
+                    .map(|(sub_pos, &block)| {
+                        let (x, y, z) = sub_pos.into();
+                        (origin + IVec3::new(x as i32, y as i32, z as i32), block)
+                    })
+                    .collect()
+            }
+            None => return,
+        };
+        let top = origin.y + Chunk::HEIGHT as i32 - 1;
+        let open_sky = self.is_open_sky(IVec3::new(origin.x, top, origin.z));
+        let (mut sky, mut block_light) = (LightQueue::new(), LightQueue::new());
+        for (world_pos, block) in blocks {
+            let emission = emission(registry, block);
+            if emission > 0 {
+                self.set_level(world_pos, Channel::Block, emission);
+                block_light.push_back((world_pos, emission));
+            }
+            if !is_transparent(registry, block) {
+                continue;
+            }
+            if open_sky && world_pos.y == top {
+                self.set_level(world_pos, Channel::Sky, MAX_LIGHT);
+                sky.push_back((world_pos, MAX_LIGHT));
+            }
+            // light coming in from loaded neighbors
+            for direction in Direction::iter() {
+                let next = world_pos + offset(direction);
+                if matches!(convert_pos(next), Some((chunk_pos, _)) if chunk_pos == pos) {
+                    continue;
+                }
+                if let Some(light) = self.light_at(next) {
+                    sky.push_back((next, light.sky()));
+                    block_light.push_back((next, light.block()));
+                }
+            }
+        }
+        self.spread_light(registry, Channel::Sky, sky);
+        self.spread_light(registry, Channel::Block, block_light);
+
+        // the section below may have been lit as open to the sky
+        let below = pos.get_neighbor(ChunkNeighbor::Down);
+        if !self.is_loaded(below) {
+            return;
+        }
+        let mut seeds = LightQueue::new();
+        for x in 0..Chunk::WIDTH as i32 {
+            for z in 0..Chunk::WIDTH as i32 {
+                let above = origin + IVec3::new(x, 0, z);
+                let next = above - IVec3::Y;
+                let sky = |light: Option<Light>| light.map(Light::sky).unwrap_or(0);
+                if sky(self.light_at(next)) == MAX_LIGHT && sky(self.light_at(above)) < MAX_LIGHT {
+                    self.set_level(next, Channel::Sky, 0);
+                    seeds.push_back((next, MAX_LIGHT));
+                }
+            }
+        }
+        let relight = self.remove_light(registry, Channel::Sky, seeds);
+        self.spread_light(registry, Channel::Sky, relight);
+    }
+
+    /// Re-propagate light around a block that was just placed or removed
+    pub fn update_light(&mut self, pos: IVec3, registry: &BlockRegistry) {
+        let block = match self.block_at(pos) {
+            Some(block) => block,
+            None => return,
+        };
+        for &channel in &CHANNELS {
+            let previous = match self.light_at(pos) {
+                Some(light) => light.get(channel),
+                None => return,
+            };
+            self.set_level(pos, channel, 0);
+            let mut queue = LightQueue::new();
+            queue.push_back((pos, previous));
+            let mut relight = self.remove_light(registry, channel, queue);
+            if is_transparent(registry, block) {
+                for direction in Direction::iter() {
+                    let next = pos + offset(direction);
+                    if let Some(light) = self.light_at(next) {
+                        relight.push_back((next, light.get(channel)));
+                    }
+                }
+                if channel == Channel::Sky && self.is_open_sky(pos) {
+                    self.set_level(pos, channel, MAX_LIGHT);
+                    relight.push_back((pos, MAX_LIGHT));
+                }
+            }
+            let emission = emission(registry, block);
+            if channel == Channel::Block && emission > 0 {
+                self.set_level(pos, channel, emission);
+                relight.push_back((pos, emission));
+            }
+            self.spread_light(registry, channel, relight);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{
+        block::test_registry,
+        chunk::BlockSubPos,
+        generator::flat::{FlatGenerator, Span},
+    };
+
+    fn sky(map: &Map, x: i32, y: i32, z: i32) -> u8 {
+        map.light_at(IVec3::new(x, y, z)).unwrap().sky()
+    }
+
+    #[test]
+    fn test_skylight_spread() {
+        let registry = test_registry();
+        let stone = registry.lookup("stone").ok();
+        // a cave below a stone ceiling at y = 15
+        let mut map = Map::new(FlatGenerator::new(&[Span(None, 15), Span(stone, 1)]));
+        map.load_chunk(ChunkPos(0, 0, 0));
+        map.light_chunk(ChunkPos(0, 0, 0), &registry);
+        assert_eq!(sky(&map, 0, 14, 0), 0);
+        assert_eq!(sky(&map, 8, 0, 8), 0);
+
+        let hole = IVec3::new(0, 15, 0);
+        map[ChunkPos(0, 0, 0)].set(BlockSubPos::new(0, 15, 0), None);
+        map.update_light(hole, &registry);
+        assert_eq!(sky(&map, 0, 15, 0), MAX_LIGHT);
+        assert_eq!(sky(&map, 0, 0, 0), MAX_LIGHT);
+        assert_eq!(sky(&map, 5, 0, 0), MAX_LIGHT - 5);
+        assert_eq!(sky(&map, 5, 0, 5), MAX_LIGHT - 10);
+        assert_eq!(sky(&map, 15, 0, 15), 0);
+    }
+
+    #[test]
+    fn test_shaft_removal() {
+        let registry = test_registry();
+        let stone = registry.lookup("stone").ok();
+        let mut map = Map::new(FlatGenerator::new(&[Span(stone, 16)]));
+        map.load_chunk(ChunkPos(0, 0, 0));
+        map.light_chunk(ChunkPos(0, 0, 0), &registry);
+        // dig an open shaft from the top, one block at a time
+        for y in (0..16).rev() {
+            map[ChunkPos(0, 0, 0)].set(BlockSubPos::new(8, y, 8), None);
+            map.update_light(IVec3::new(8, y as i32, 8), &registry);
+        }
+        assert!((0..16).all(|y| sky(&map, 8, y, 8) == MAX_LIGHT));
+
+        // closing the shaft darkens everything below
+        map[ChunkPos(0, 0, 0)].set(BlockSubPos::new(8, 10, 8), stone);
+        map.update_light(IVec3::new(8, 10, 8), &registry);
+        assert_eq!(sky(&map, 8, 10, 8), 0);
+        assert!((0..10).all(|y| sky(&map, 8, y, 8) == 0));
+        assert!((11..16).all(|y| sky(&map, 8, y, 8) == MAX_LIGHT));
+
+        // and opening it again lights it up
+        map[ChunkPos(0, 0, 0)].set(BlockSubPos::new(8, 10, 8), None);
+        map.update_light(IVec3::new(8, 10, 8), &registry);
+        assert!((0..16).all(|y| sky(&map, 8, y, 8) == MAX_LIGHT));
+    }
+
+    #[test]
+    fn test_block_light() {
+        let registry = test_registry();
+        let stone = registry.lookup("stone").ok();
+        let lamp = registry.lookup("lamp").ok();
+        let level = registry[lamp.unwrap()].data.emission();
+        let mut map = Map::new(FlatGenerator::new(&[Span(stone, 16)]));
+        map.load_chunk(ChunkPos(0, 0, 0));
+        map.light_chunk(ChunkPos(0, 0, 0), &registry);
+        // a closed tunnel along x with a lamp at its end
+        for x in 0..16 {
+            map[ChunkPos(0, 0, 0)].set(BlockSubPos::new(x, 8, 8), None);
+            map.update_light(IVec3::new(x as i32, 8, 8), &registry);
+        }
+        map[ChunkPos(0, 0, 0)].set(BlockSubPos::new(0, 8, 8), lamp);
+        map.update_light(IVec3::new(0, 8, 8), &registry);
+        let block = |x| map.light_at(IVec3::new(x, 8, 8)).unwrap().block();
+        assert_eq!(block(0), level);
+        assert_eq!(block(5), level - 5);
+        assert_eq!(map.light_at(IVec3::new(5, 8, 8)).unwrap().sky(), 0);
+
+        map[ChunkPos(0, 0, 0)].set(BlockSubPos::new(0, 8, 8), None);
+        map.update_light(IVec3::new(0, 8, 8), &registry);
+        let block = |x| map.light_at(IVec3::new(x, 8, 8)).unwrap().block();
+        assert!((0..16).all(|x| block(x) == 0));
+    }
+
+    #[test]
+    fn test_section_above() {
+        let registry = test_registry();
+        let stone = registry.lookup("stone").ok();
+        // sections are lit bottom first, then covered by a stone layer
+        let mut map = Map::new(FlatGenerator::new(&[Span(None, 20), Span(stone, 1)]));
+        map.load_chunk(ChunkPos(0, 0, 0));
+        map.light_chunk(ChunkPos(0, 0, 0), &registry);
+        assert_eq!(sky(&map, 3, 3, 3), MAX_LIGHT);
+        map.load_chunk(ChunkPos(0, 1, 0));
+        map.light_chunk(ChunkPos(0, 1, 0), &registry);
+        assert_eq!(sky(&map, 3, 3, 3), 0);
+        assert_eq!(sky(&map, 3, 19, 3), 0);
+    }
+}
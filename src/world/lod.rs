@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use glam::IVec3;
+use strum_macros::EnumIter;
+
+use super::{
+    block::BlockId,
+    chunk::{BlockSubPos, Chunk},
+    light::Light,
+};
+
+/// Detail a chunk is meshed at, coarser levels draw cells of several blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, EnumIter)]
+pub enum Lod {
+    Full,
+    Half,
+    Quarter,
+}
+
+impl Lod {
+    /// Distance in blocks from which chunks are drawn at half detail
+    pub const HALF_DISTANCE: f32 = 32.0;
+    /// Distance in blocks from which chunks are drawn at quarter detail
+    pub const QUARTER_DISTANCE: f32 = 64.0;
+
+    /// Blocks per cell along each axis
+    pub fn scale(self) -> i32 {
+        match self {
+            Lod::Full => 1,
+            Lod::Half => 2,
+            Lod::Quarter => 4,
+        }
+    }
+
+    /// Level for something `distance` blocks away from the camera
+    pub fn for_distance(distance: f32) -> Self {
+        if distance < Self::HALF_DISTANCE {
+            Lod::Full
+        } else if distance < Self::QUARTER_DISTANCE {
+            Lod::Half
+        } else {
+            Lod::Quarter
+        }
+    }
+}
+
+/// Blocks of a chunk merged into cells of [`Lod::scale`] blocks
+pub struct LodGrid {
+    size: IVec3,
+    blocks: Vec<Option<BlockId>>,
+    light: Vec<Light>,
+}
+
+impl LodGrid {
+    /// Cells along each axis
+    pub fn size(&self) -> IVec3 {
+        self.size
+    }
+
+    fn index(&self, cell: IVec3) -> usize {
+        (cell.x + self.size.x * (cell.z + self.size.z * cell.y)) as usize
+    }
+
+    pub fn block(&self, cell: IVec3) -> Option<BlockId> {
+        self.blocks[self.index(cell)]
+    }
+
+    pub fn light(&self, cell: IVec3) -> Light {
+        self.light[self.index(cell)]
+    }
+}
+
+impl Chunk {
+    /// Downsample to cells of `lod.scale()` blocks
+    ///
+    /// A cell is solid when at least half of its blocks are, and shows the
+    /// most common block of its highest non-empty layer so surfaces keep
+    /// their look. It takes the brightest light of its blocks, which is the
+    /// light of the air in it.
+    pub fn downsample(&self, lod: Lod) -> LodGrid {
+        let scale = lod.scale();
+        let size = IVec3::new(
+            Chunk::WIDTH as i32 / scale,
+            Chunk::HEIGHT as i32 / scale,
+            Chunk::WIDTH as i32 / scale,
+        );
+        let mut grid = LodGrid {
+            size,
+            blocks: Vec::with_capacity((size.x * size.y * size.z) as usize),
+            light: Vec::with_capacity((size.x * size.y * size.z) as usize),
+        };
+        for (y, z, x) in itertools::iproduct!(0..size.y, 0..size.z, 0..size.x) {
+            let origin = IVec3::new(x, y, z) * scale;
+            let mut solid = 0;
+            let (mut sky, mut block_light) = (0, 0);
+            // highest layer wins, so count from the top down
+            let mut top: Option<HashMap<BlockId, i32>> = None;
+            for dy in (0..scale).rev() {
+                let mut layer = HashMap::new();
+                for (dz, dx) in itertools::iproduct!(0..scale, 0..scale) {
+                    let pos = origin + IVec3::new(dx, dy, dz);
+                    let pos = BlockSubPos::new(pos.x as u8, pos.y as u8, pos.z as u8);
+                    let light = self.light(pos);
+                    sky = sky.max(light.sky());
+                    block_light = block_light.max(light.block());
+                    if let Some(block) = self[pos] {
+                        solid += 1;
+                        *layer.entry(block).or_insert(0) += 1;
+                    }
+                }
+                if top.is_none() && !layer.is_empty() {
+                    top = Some(layer);
+                }
+            }
+            let block = match top {
+                Some(layer) if solid * 2 >= scale * scale * scale => layer
+                    .into_iter()
+                    .max_by_key(|&(block, count)| (count, block))
+                    .map(|(block, _)| block),
+                _ => None,
+            };
+            grid.blocks.push(block);
+            grid.light.push(Light::new(sky, block_light));
+        }
+        grid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::block::test_registry;
+
+    #[test]
+    fn test_for_distance() {
+        assert_eq!(Lod::for_distance(0.0), Lod::Full);
+        assert_eq!(Lod::for_distance(Lod::HALF_DISTANCE), Lod::Half);
+        assert_eq!(Lod::for_distance(1000.0), Lod::Quarter);
+    }
+
+    #[test]
+    fn test_downsample() {
+        let registry = test_registry();
+        let stone = registry.lookup("stone").ok();
+        let grass = registry.lookup("grass").ok();
+        // stone up to y = 4, grass on top, a lone block floating at y = 12
+        let chunk = Chunk::from_fn(|pos| {
+            let (x, y, z): (u8, u8, u8) = pos.into();
+            match y {
+                0..=4 => stone,
+                5 => grass,
+                12 if (x, z) == (0, 0) => stone,
+                _ => None,
+            }
+        });
+        let grid = chunk.downsample(Lod::Half);
+        assert_eq!(grid.size(), IVec3::new(8, 8, 8));
+        assert_eq!(grid.block(IVec3::new(3, 0, 3)), stone);
+        // half stone and half grass, grass is on top
+        assert_eq!(grid.block(IVec3::new(3, 2, 3)), grass);
+        // mostly air
+        assert_eq!(grid.block(IVec3::new(0, 6, 0)), None);
+
+        let grid = chunk.downsample(Lod::Quarter);
+        assert_eq!(grid.size(), IVec3::new(4, 4, 4));
+        assert_eq!(grid.block(IVec3::new(0, 1, 0)), grass);
+        assert_eq!(grid.block(IVec3::new(0, 2, 0)), None);
+    }
+}
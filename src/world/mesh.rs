@@ -0,0 +1,664 @@
+use std::collections::{BTreeSet, HashMap};
+
+use enum_map::EnumMap;
+use glam::IVec3;
+use rayon::prelude::*;
+use strum::IntoEnumIterator;
+
+use crate::{
+    common::direction::Direction,
+    math::axis::{Axis, HasAxis, HasAxisMut},
+};
+
+use super::{
+    block::{BlockId, BlockRegistry, BlockType},
+    chunk::Chunk,
+    convert_pos,
+    fluid::{offset, FluidLevel},
+    light::{Light, MAX_LIGHT},
+    lod::Lod,
+    ChunkPos, Map,
+};
+
+/// Visible block face, or a rectangle of merged faces
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quad {
+    /// World position of the block at the minimum corner
+    pub position: IVec3,
+    pub direction: Direction,
+    /// Covered blocks along each axis, the cell size along the face normal
+    pub extent: IVec3,
+    pub block: BlockId,
+    /// Light in front of the face
+    pub light: Light,
+    /// Below 1.0 for fluid that isn't a full block
+    pub height: f32,
+    /// Ambient occlusion at each corner, in the order `cube.geom` emits
+    /// them, from 0 between two walls to 3 in the open
+    pub ao: [u8; 4],
+}
+
+impl Quad {
+    /// Number of block faces covered, the extent along the normal is the
+    /// cell depth and doesn't count
+    pub fn area(&self) -> i32 {
+        let [a, b] = self.direction.axis().rest();
+        self.extent.get_axis(a) * self.extent.get_axis(b)
+    }
+
+    /// World positions of the blocks the quad belongs to
+    pub fn blocks(&self) -> impl Iterator<Item = IVec3> {
+        let (position, extent) = (self.position, self.extent);
+        itertools::iproduct!(0..extent.x, 0..extent.y, 0..extent.z)
+            .map(move |(x, y, z)| position + IVec3::new(x, y, z))
+    }
+
+    /// Faces with the same block, light, occlusion and shape look the same,
+    /// so they can share a quad
+    fn merges_with(&self, rhs: &Quad) -> bool {
+        self.block == rhs.block
+            && self.light == rhs.light
+            && self.height == rhs.height
+            && self.ao == rhs.ao
+    }
+}
+
+/// Faces pointing one way in one layer of a chunk, meshes are rebuilt a
+/// slice at a time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MeshSlice {
+    pub direction: Direction,
+    /// Position along the face normal, in cells of the chunk's [`Lod`]
+    pub layer: i32,
+}
+
+impl MeshSlice {
+    /// Every slice of a chunk meshed at `lod`
+    pub fn all(lod: Lod) -> impl Iterator<Item = Self> {
+        Direction::iter().flat_map(move |direction| {
+            (0..cells(direction.axis(), lod)).map(move |layer| MeshSlice { direction, layer })
+        })
+    }
+
+    /// Slice at the `direction` side of a chunk meshed at `lod`
+    pub fn boundary(direction: Direction, lod: Lod) -> Self {
+        let layer = if direction.is_positive() {
+            cells(direction.axis(), lod) - 1
+        } else {
+            0
+        };
+        MeshSlice { direction, layer }
+    }
+}
+
+fn size(axis: Axis) -> i32 {
+    match axis {
+        Axis::Y => Chunk::HEIGHT as i32,
+        Axis::X | Axis::Z => Chunk::WIDTH as i32,
+    }
+}
+
+fn cells(axis: Axis, lod: Lod) -> i32 {
+    size(axis) / lod.scale()
+}
+
+/// Corners of each face in the order `cube.geom` emits them, relative to the
+/// minimum corner of the block
+const CORNERS: [[[i32; 3]; 4]; 6] = [
+    // North
+    [[1, 0, 0], [0, 0, 0], [1, 1, 0], [0, 1, 0]],
+    // South
+    [[0, 0, 1], [1, 0, 1], [0, 1, 1], [1, 1, 1]],
+    // East
+    [[1, 0, 1], [1, 0, 0], [1, 1, 1], [1, 1, 0]],
+    // West
+    [[0, 0, 0], [0, 0, 1], [0, 1, 0], [0, 1, 1]],
+    // Up
+    [[0, 1, 1], [1, 1, 1], [0, 1, 0], [1, 1, 0]],
+    // Down
+    [[0, 0, 0], [1, 0, 0], [0, 0, 1], [1, 0, 1]],
+];
+
+/// Copy of a chunk and the blocks around it, so it can be meshed away from
+/// the [`Map`]
+pub struct ChunkSnapshot {
+    pos: ChunkPos,
+    revision: u64,
+    /// Positions below are cells of this level
+    lod: Lod,
+    /// Sides towards chunks drawn at another [`Lod`], their faces are kept
+    /// to cover the gaps between the two meshes
+    seams: EnumMap<Direction, bool>,
+    /// Chunk padded by one cell on every side, `None` where the chunk isn't
+    /// loaded
+    blocks: Vec<Option<Option<BlockId>>>,
+    light: Vec<Light>,
+    fluid: Vec<FluidLevel>,
+}
+
+impl ChunkSnapshot {
+    fn padded(lod: Lod) -> IVec3 {
+        IVec3::new(
+            cells(Axis::X, lod),
+            cells(Axis::Y, lod),
+            cells(Axis::Z, lod),
+        ) + IVec3::splat(2)
+    }
+
+    /// Index of a local cell, `-1..=size` on every axis
+    fn index(&self, local: IVec3) -> usize {
+        let padded = Self::padded(self.lod);
+        let local = local + IVec3::ONE;
+        (local.x + padded.x * (local.z + padded.z * local.y)) as usize
+    }
+
+    fn new(pos: ChunkPos, revision: u64, lod: Lod) -> Self {
+        let padded = Self::padded(lod);
+        let count = (padded.x * padded.y * padded.z) as usize;
+        Self {
+            pos,
+            revision,
+            lod,
+            seams: EnumMap::default(),
+            blocks: vec![None; count],
+            light: vec![Light::default(); count],
+            fluid: vec![FluidLevel::SOURCE; count],
+        }
+    }
+
+    /// Keep the faces on the `seams` sides
+    pub fn with_seams(mut self, seams: EnumMap<Direction, bool>) -> Self {
+        self.seams = seams;
+        self
+    }
+
+    pub fn pos(&self) -> ChunkPos {
+        self.pos
+    }
+
+    pub fn lod(&self) -> Lod {
+        self.lod
+    }
+
+    /// Newest revision of the chunks copied, a larger revision means newer
+    /// content
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    fn gen_face(
+        &self,
+        registry: &BlockRegistry,
+        local: IVec3,
+        direction: Direction,
+    ) -> Option<Quad> {
+        let block = self.blocks[self.index(local)]??;
+        let neighbor = local + offset(direction);
+        let axis = direction.axis();
+        let outside = !(0..cells(axis, self.lod)).contains(neighbor.get_axis(axis));
+        let (neighbor, light) = match self.blocks[self.index(neighbor)] {
+            Some(_) if outside && self.seams[direction] => (None, self.light[self.index(neighbor)]),
+            Some(blk) => (blk, self.light[self.index(neighbor)]),
+            // faces towards chunks that aren't ready stay visible until the
+            // neighbor shows up and marks this chunk dirty
+            None => (None, Light::SKY),
+        };
+        if registry.is_face_hidden(block, neighbor) {
+            return None;
+        }
+        let data = &registry[block].data;
+        // light sources show their own color regardless of surroundings
+        let (light, ao) = match data {
+            BlockType::Emissive { .. } => (Light::new(MAX_LIGHT, MAX_LIGHT), [3; 4]),
+            _ => (light, self.occlusion(registry, local, direction)),
+        };
+        let height = match *data {
+            BlockType::Fluid { spread, .. } => self.fluid[self.index(local)].height(spread),
+            _ => 1.0,
+        };
+        let scale = self.lod.scale();
+        Some(Quad {
+            position: self.pos.origin() + local * scale,
+            direction,
+            extent: IVec3::splat(scale),
+            block,
+            light,
+            height,
+            ao,
+        })
+    }
+
+    /// Classic voxel occlusion, each corner of a face is darkened by the
+    /// opaque blocks next to it in the layer in front of the face
+    fn occlusion(&self, registry: &BlockRegistry, local: IVec3, direction: Direction) -> [u8; 4] {
+        let front = local + offset(direction);
+        let [u, v] = direction.axis().rest();
+        let opaque = |pos: IVec3| match self.blocks[self.index(pos)] {
+            Some(Some(block)) => registry[block].data.is_opaque(),
+            // chunks that aren't ready don't cast shadows
+            _ => false,
+        };
+        let mut ao = [3; 4];
+        for (ao, corner) in ao.iter_mut().zip(&CORNERS[direction as usize]) {
+            let corner = IVec3::from(*corner);
+            let toward = |axis| {
+                let mut step = IVec3::ZERO;
+                step.set_axis(axis, corner.get_axis(axis) * 2 - 1);
+                step
+            };
+            let side_u = opaque(front + toward(u));
+            let side_v = opaque(front + toward(v));
+            let diagonal = opaque(front + toward(u) + toward(v));
+            // the diagonal is hidden behind two walls
+            *ao = if side_u && side_v {
+                0
+            } else {
+                3 - side_u as u8 - side_v as u8 - diagonal as u8
+            };
+        }
+        ao
+    }
+
+    /// One quad per visible face
+    pub fn chunk_faces(&self, registry: &BlockRegistry) -> Vec<Quad> {
+        let size = Self::padded(self.lod) - IVec3::splat(2);
+        itertools::iproduct!(0..size.x, 0..size.y, 0..size.z, Direction::iter())
+            .filter_map(|(x, y, z, direction)| {
+                self.gen_face(registry, IVec3::new(x, y, z), direction)
+            })
+            .collect()
+    }
+
+    /// Visible faces of one slice, with neighbors that look the same merged
+    /// into larger quads
+    ///
+    /// Translucent faces and partial fluid faces are kept one per block, they
+    /// are sorted and shaped per block when drawn.
+    pub fn mesh_slice(&self, registry: &BlockRegistry, slice: MeshSlice) -> Vec<Quad> {
+        let normal = slice.direction.axis();
+        let [u, v] = normal.rest();
+        let (width, height) = (cells(u, self.lod), cells(v, self.lod));
+        let local = |a: i32, b: i32| {
+            let mut local = IVec3::ZERO;
+            local.set_axis(normal, slice.layer);
+            local.set_axis(u, a);
+            local.set_axis(v, b);
+            local
+        };
+        let index = |a: i32, b: i32| (a + b * width) as usize;
+        let mut quads = Vec::new();
+        let mut grid: Vec<Option<Quad>> = vec![None; (width * height) as usize];
+        for b in 0..height {
+            for a in 0..width {
+                match self.gen_face(registry, local(a, b), slice.direction) {
+                    Some(face) if face.height < 1.0 => quads.push(face),
+                    Some(face) if registry[face.block].data.is_translucent() => quads.push(face),
+                    face => grid[index(a, b)] = face,
+                }
+            }
+        }
+        for b in 0..height {
+            for a in 0..width {
+                let first = match grid[index(a, b)] {
+                    Some(first) => first,
+                    None => continue,
+                };
+                let same = |face: &Option<Quad>| match face {
+                    Some(face) => first.merges_with(face),
+                    None => false,
+                };
+                let mut w = 1;
+                while a + w < width && same(&grid[index(a + w, b)]) {
+                    w += 1;
+                }
+                let mut h = 1;
+                while b + h < height && (a..a + w).all(|a| same(&grid[index(a, b + h)])) {
+                    h += 1;
+                }
+                for b in b..b + h {
+                    for a in a..a + w {
+                        grid[index(a, b)] = None;
+                    }
+                }
+                let mut quad = first;
+                quad.extent.set_axis(u, w * self.lod.scale());
+                quad.extent.set_axis(v, h * self.lod.scale());
+                quads.push(quad);
+            }
+        }
+        quads
+    }
+
+    /// Every slice merged by [`ChunkSnapshot::mesh_slice`]
+    pub fn greedy_mesh(&self, registry: &BlockRegistry) -> Vec<Quad> {
+        let slices: Vec<_> = MeshSlice::all(self.lod).collect();
+        slices
+            .par_iter()
+            .flat_map_iter(|&slice| self.mesh_slice(registry, slice))
+            .collect()
+    }
+}
+
+impl Map {
+    /// Copy a loaded chunk with the blocks around it
+    pub fn snapshot(&self, chunk_pos: ChunkPos) -> Option<ChunkSnapshot> {
+        let origin = chunk_pos.origin();
+        let padded = ChunkSnapshot::padded(Lod::Full);
+        let revision = self.get(chunk_pos)?.revision();
+        let mut snapshot = ChunkSnapshot::new(chunk_pos, revision, Lod::Full);
+        // every position falls into one of the 3 * 3 * 3 chunks around
+        let mut chunks = HashMap::new();
+        for (y, z, x) in itertools::iproduct!(-1..padded.y - 1, -1..padded.z - 1, -1..padded.x - 1)
+        {
+            let local = IVec3::new(x, y, z);
+            let (pos, sub_pos) = match convert_pos(origin + local) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            let chunk = match *chunks.entry(pos).or_insert_with(|| self.get(pos)) {
+                Some(chunk) => chunk,
+                None => continue,
+            };
+            let index = snapshot.index(local);
+            snapshot.blocks[index] = Some(chunk[sub_pos]);
+            snapshot.light[index] = chunk.light(sub_pos);
+            snapshot.fluid[index] = chunk.fluid(sub_pos);
+        }
+        snapshot.revision = chunks
+            .values()
+            .flatten()
+            .map(|chunk| chunk.revision())
+            .fold(snapshot.revision, u64::max);
+        Some(snapshot)
+    }
+
+    /// Copy a loaded chunk downsampled to `lod`, with the cells of the chunks
+    /// next to it
+    pub fn lod_snapshot(&self, chunk_pos: ChunkPos, lod: Lod) -> Option<ChunkSnapshot> {
+        if lod == Lod::Full {
+            return self.snapshot(chunk_pos);
+        }
+        let chunk = self.get(chunk_pos)?;
+        let mut snapshot = ChunkSnapshot::new(chunk_pos, chunk.revision(), lod);
+        let mut copy = |chunk: &Chunk, offset: IVec3| {
+            let grid = chunk.downsample(lod);
+            let size = grid.size();
+            for (y, z, x) in itertools::iproduct!(0..size.y, 0..size.z, 0..size.x) {
+                let cell = IVec3::new(x, y, z);
+                let local = cell + offset * size;
+                // only the layer next to the chunk is kept from neighbors
+                if (local.cmplt(IVec3::splat(-1)) | local.cmpgt(size)).any() {
+                    continue;
+                }
+                let index = snapshot.index(local);
+                snapshot.blocks[index] = Some(grid.block(cell));
+                snapshot.light[index] = grid.light(cell);
+            }
+            snapshot.revision = snapshot.revision.max(chunk.revision());
+        };
+        copy(chunk, IVec3::ZERO);
+        // faces only look straight ahead, corners aren't needed
+        for direction in Direction::iter() {
+            let neighbor = chunk_pos.get_neighbor(direction.into());
+            if let Some(neighbor) = self.get(neighbor) {
+                copy(neighbor, offset(direction));
+            }
+        }
+        Some(snapshot)
+    }
+
+    /// Slices of a loaded chunk whose faces may have changed since it and
+    /// its neighbors were marked clean
+    ///
+    /// A changed block affects its own faces and the faces of the blocks
+    /// around it, either hidden by it or shaded by it. Neighbors, diagonal
+    /// ones included, only matter when their changes reach the shared sides.
+    pub fn dirty_slices(&self, chunk_pos: ChunkPos) -> BTreeSet<MeshSlice> {
+        let mut slices = BTreeSet::new();
+        if !self.is_loaded(chunk_pos) {
+            return slices;
+        }
+        let size = IVec3::new(size(Axis::X), size(Axis::Y), size(Axis::Z));
+        let ChunkPos(x, y, z) = chunk_pos;
+        for (dx, dy, dz) in itertools::iproduct!(-1..=1, -1..=1, -1..=1) {
+            let region = match self.get(ChunkPos(x + dx, y + dy, z + dz)) {
+                Some(chunk) => chunk.dirty_region(),
+                None => None,
+            };
+            let region = match region {
+                Some(region) => region,
+                None => continue,
+            };
+            // local to this chunk, only the layer around it is of interest
+            let shift = IVec3::new(dx, dy, dz) * size;
+            let min = (region.min + shift).max(IVec3::splat(-1));
+            let max = (region.max + shift).min(size);
+            if (min.cmpgt(max)).any() {
+                continue;
+            }
+            for direction in Direction::iter() {
+                let axis = direction.axis();
+                let (min, max) = (*min.get_axis(axis), *max.get_axis(axis));
+                // the block behind a changed one faces it
+                let (min, max) = if direction.is_positive() {
+                    ((min - 1).max(0), max.min(size.get_axis(axis) - 1))
+                } else {
+                    (min.max(0), (max + 1).min(size.get_axis(axis) - 1))
+                };
+                slices.extend((min..=max).map(|layer| MeshSlice { direction, layer }));
+            }
+        }
+        slices
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::world::{
+        block::test_registry,
+        generator::flat::{FlatGenerator, Span},
+    };
+
+    /// Every covered block face with what it looks like
+    fn coverage(quads: &[Quad]) -> HashMap<(IVec3, Direction), (BlockId, Light)> {
+        let mut coverage = HashMap::new();
+        for quad in quads {
+            for pos in quad.blocks() {
+                let prev = coverage.insert((pos, quad.direction), (quad.block, quad.light));
+                assert!(
+                    prev.is_none(),
+                    "face {} {:?} covered twice",
+                    pos,
+                    quad.direction
+                );
+            }
+        }
+        coverage
+    }
+
+    #[test]
+    fn test_greedy_mesh() {
+        let registry = test_registry();
+        let block = |name| registry.lookup(name).ok();
+        let ground = [Span(block("stone"), 3), Span(block("grass"), 1)];
+        let mut map = Map::new(FlatGenerator::new(&ground));
+        let pos = ChunkPos(0, 0, 0);
+        map.load_chunk(pos);
+        map.set_block(IVec3::new(5, 4, 5), block("dirt"));
+        map.set_block(IVec3::new(9, 4, 2), block("glass"));
+
+        let snapshot = map.snapshot(pos).unwrap();
+        let faces = snapshot.chunk_faces(&registry);
+        let quads = snapshot.greedy_mesh(&registry);
+        assert!(
+            quads.len() * 10 < faces.len(),
+            "{} quads for {} faces",
+            quads.len(),
+            faces.len()
+        );
+        assert_eq!(
+            quads.iter().map(Quad::area).sum::<i32>() as usize,
+            faces.len()
+        );
+        assert_eq!(coverage(&quads), coverage(&faces));
+    }
+
+    fn mark_all_clean(map: &Map) {
+        for (_, chunk) in map.iter() {
+            chunk.mark_clean();
+        }
+    }
+
+    #[test]
+    fn test_dirty_slices() {
+        let registry = test_registry();
+        let block = |name| registry.lookup(name).ok();
+        let ground = [Span(block("stone"), 3), Span(block("grass"), 1)];
+        let mut map = Map::new(FlatGenerator::new(&ground));
+        let (pos, west) = (ChunkPos(0, 0, 0), ChunkPos(-1, 0, 0));
+        map.load_chunk(pos);
+        map.load_chunk(west);
+        mark_all_clean(&map);
+        assert!(map.dirty_slices(pos).is_empty());
+
+        map.set_block(IVec3::new(5, 4, 5), block("dirt"));
+        let slices = map.dirty_slices(pos);
+        let up = |layer| MeshSlice {
+            direction: Direction::Up,
+            layer,
+        };
+        assert!(slices.contains(&up(3)) && slices.contains(&up(4)));
+        assert!(!slices.contains(&up(5)));
+        assert_eq!(slices.len(), 12);
+        assert!(map.dirty_slices(west).is_empty());
+        mark_all_clean(&map);
+
+        // edits on the side hide and shade faces of the neighbor
+        map.set_block(IVec3::new(0, 4, 5), block("dirt"));
+        let east = MeshSlice {
+            direction: Direction::East,
+            layer: Chunk::WIDTH as i32 - 1,
+        };
+        let slices = map.dirty_slices(west);
+        let sides: Vec<_> = slices
+            .iter()
+            .filter(|slice| slice.direction.axis() == Axis::X)
+            .collect();
+        assert_eq!(sides, vec![&east]);
+        assert!(slices.contains(&up(3)));
+    }
+
+    #[test]
+    fn test_incremental_mesh() {
+        let registry = test_registry();
+        let block = |name| registry.lookup(name).ok();
+        let ground = [Span(block("stone"), 3), Span(block("grass"), 1)];
+        let mut map = Map::new(FlatGenerator::new(&ground));
+        let pos = ChunkPos(0, 0, 0);
+        map.load_chunk(pos);
+        let snapshot = map.snapshot(pos).unwrap();
+        let mut slices: HashMap<MeshSlice, Vec<Quad>> = MeshSlice::all(Lod::Full)
+            .map(|slice| (slice, snapshot.mesh_slice(&registry, slice)))
+            .collect();
+        mark_all_clean(&map);
+
+        map.set_block(IVec3::new(5, 4, 5), block("dirt"));
+        map.set_block(IVec3::new(7, 3, 7), None);
+        let snapshot = map.snapshot(pos).unwrap();
+        for slice in map.dirty_slices(pos) {
+            slices.insert(slice, snapshot.mesh_slice(&registry, slice));
+        }
+        let quads: Vec<Quad> = slices.values().flatten().copied().collect();
+        assert_eq!(coverage(&quads), coverage(&snapshot.greedy_mesh(&registry)));
+    }
+
+    #[test]
+    fn test_occlusion() {
+        let registry = test_registry();
+        let block = |name| registry.lookup(name).ok();
+        let ground = [Span(block("stone"), 3), Span(block("grass"), 1)];
+        let mut map = Map::new(FlatGenerator::new(&ground));
+        let (pos, east) = (ChunkPos(0, 0, 0), ChunkPos(1, 0, 0));
+        map.load_chunk(pos);
+        map.load_chunk(east);
+        let ao = |map: &Map, x, z| {
+            let faces = map.snapshot(pos).unwrap().chunk_faces(&registry);
+            let position = IVec3::new(x, 3, z);
+            let face = faces
+                .iter()
+                .find(|face| face.position == position && face.direction == Direction::Up);
+            face.unwrap().ao
+        };
+        assert_eq!(ao(&map, 5, 5), [3; 4]);
+
+        // up faces go (0, 1), (1, 1), (0, 0), (1, 0) along x and z
+        map.set_block(IVec3::new(5, 4, 5), block("stone"));
+        assert_eq!(ao(&map, 6, 5), [2, 3, 2, 3]);
+        assert_eq!(ao(&map, 6, 6), [3, 3, 2, 3]);
+        // walls on two sides hide the corner between them
+        map.set_block(IVec3::new(6, 4, 4), block("stone"));
+        assert_eq!(ao(&map, 6, 5), [2, 3, 0, 2]);
+        // only opaque blocks cast shadows
+        map.set_block(IVec3::new(8, 4, 8), block("glass"));
+        assert_eq!(ao(&map, 9, 8), [3; 4]);
+
+        // blocks of the next chunk shade the side of this one
+        map.set_block(IVec3::new(16, 4, 5), block("stone"));
+        assert_eq!(ao(&map, 15, 5), [3, 2, 3, 2]);
+
+        // faces that are shaded differently stay apart
+        let quads = map.snapshot(pos).unwrap().greedy_mesh(&registry);
+        let shaded: Vec<_> = quads
+            .iter()
+            .filter(|quad| quad.direction == Direction::Up && quad.ao != [3; 4])
+            .collect();
+        assert!(!shaded.is_empty());
+        assert!(shaded.iter().all(|quad| quad.area() == 1));
+    }
+
+    #[test]
+    fn test_lod_mesh() {
+        let registry = test_registry();
+        let block = |name| registry.lookup(name).ok();
+        let ground = [Span(block("stone"), 3), Span(block("grass"), 1)];
+        let mut map = Map::new(FlatGenerator::new(&ground));
+        let (pos, east) = (ChunkPos(0, 0, 0), ChunkPos(1, 0, 0));
+        map.load_chunk(pos);
+        map.load_chunk(east);
+
+        let full = map.snapshot(pos).unwrap().greedy_mesh(&registry);
+        let snapshot = map.lod_snapshot(pos, Lod::Half).unwrap();
+        let quads = snapshot.greedy_mesh(&registry);
+        assert!(quads.len() <= full.len());
+        let even = |v: IVec3| <[i32; 3]>::from(v).iter().all(|c| c % 2 == 0);
+        assert!(quads
+            .iter()
+            .all(|quad| even(quad.position) && even(quad.extent)));
+        // the surface is still covered, and keeps its top block
+        let top: Vec<_> = quads
+            .iter()
+            .filter(|quad| quad.direction == Direction::Up)
+            .collect();
+        let area: i32 = top.iter().map(|quad| quad.area()).sum();
+        assert_eq!(area, (Chunk::WIDTH * Chunk::WIDTH) as i32);
+        assert!(top.iter().all(|quad| quad.position.y == 2));
+        assert!(top.iter().all(|quad| Some(quad.block) == block("grass")));
+
+        // faces towards a neighbor at another level are kept
+        let side = |quads: &[Quad]| -> i32 {
+            quads
+                .iter()
+                .filter(|quad| quad.direction == Direction::East)
+                .map(Quad::area)
+                .sum()
+        };
+        assert_eq!(side(&quads), 0);
+        let mut seams = EnumMap::default();
+        seams[Direction::East] = true;
+        let quads = snapshot.with_seams(seams).greedy_mesh(&registry);
+        assert_eq!(side(&quads), 4 * Chunk::WIDTH as i32);
+    }
+}
@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use crossbeam_channel::{Receiver, Sender};
+
+use super::{
+    block::BlockRegistry,
+    lod::Lod,
+    mesh::{ChunkSnapshot, MeshSlice, Quad},
+    ChunkPos,
+};
+
+/// Slices of a chunk finished by a [`MeshQueue`] worker
+pub struct MeshUpdate<V> {
+    pub pos: ChunkPos,
+    /// Revision of the snapshot the slices were built from
+    pub revision: u64,
+    /// Number of the job, later jobs were made from newer snapshots
+    pub job: u64,
+    pub lod: Lod,
+    pub slices: Vec<(MeshSlice, Vec<V>)>,
+}
+
+/// Turns a quad into whatever the caller uploads
+type VertexFn<V> = Arc<dyn Fn(&BlockRegistry, &Quad) -> V + Send + Sync>;
+
+/// Meshes chunk snapshots on the rayon pool
+///
+/// Workers also turn the quads into vertices, so the render thread only has
+/// to upload them. Updates come back in any order, callers keep the slice
+/// from the latest job.
+pub struct MeshQueue<V> {
+    registry: Arc<BlockRegistry>,
+    vertex: VertexFn<V>,
+    in_flight: usize,
+    next_job: u64,
+    sender: Sender<MeshUpdate<V>>,
+    receiver: Receiver<MeshUpdate<V>>,
+}
+
+impl<V: Send + 'static> MeshQueue<V> {
+    pub fn new<F>(registry: Arc<BlockRegistry>, vertex: F) -> Self
+    where
+        F: Fn(&BlockRegistry, &Quad) -> V + Send + Sync + 'static,
+    {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        Self {
+            registry,
+            vertex: Arc::new(vertex),
+            in_flight: 0,
+            next_job: 0,
+            sender,
+            receiver,
+        }
+    }
+
+    /// Jobs submitted but not collected yet
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    pub fn submit(&mut self, snapshot: ChunkSnapshot, slices: Vec<MeshSlice>) {
+        self.in_flight += 1;
+        let job = self.next_job;
+        self.next_job += 1;
+        let registry = self.registry.clone();
+        let vertex = self.vertex.clone();
+        let sender = self.sender.clone();
+        rayon::spawn(move || {
+            let slices = slices
+                .into_iter()
+                .map(|slice| {
+                    let quads = snapshot.mesh_slice(&registry, slice);
+                    let vertices = quads.iter().map(|quad| vertex(&registry, quad)).collect();
+                    (slice, vertices)
+                })
+                .collect();
+            // receiver lives as long as the queue, nothing to do if it's gone
+            let _ = sender.send(MeshUpdate {
+                pos: snapshot.pos(),
+                revision: snapshot.revision(),
+                job,
+                lod: snapshot.lod(),
+                slices,
+            });
+        });
+    }
+
+    /// Collect finished jobs
+    pub fn poll(&mut self) -> Vec<MeshUpdate<V>> {
+        let finished: Vec<_> = self.receiver.try_iter().collect();
+        self.in_flight -= finished.len();
+        finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use glam::IVec3;
+
+    use super::*;
+    use crate::world::{
+        block::test_registry,
+        generator::flat::{FlatGenerator, Span},
+        Map,
+    };
+
+    #[test]
+    fn test_mesh_queue() {
+        let registry = test_registry();
+        let block = |name| registry.lookup(name).ok();
+        let ground = [Span(block("stone"), 3), Span(block("grass"), 1)];
+        let mut map = Map::new(FlatGenerator::new(&ground));
+        let pos = ChunkPos(0, 0, 0);
+        map.load_chunk(pos);
+        let first = map.snapshot(pos).unwrap();
+        map.set_block(IVec3::new(5, 4, 5), block("dirt"));
+        let second = map.snapshot(pos).unwrap();
+        assert!(second.revision() > first.revision());
+
+        let expected = second.greedy_mesh(&registry);
+        let mut queue = MeshQueue::new(Arc::new(registry), |_, quad| *quad);
+        queue.submit(second, MeshSlice::all(Lod::Full).collect());
+        let mut updates = Vec::new();
+        while queue.in_flight() > 0 {
+            updates.extend(queue.poll());
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].pos, pos);
+        let quads: Vec<Quad> = updates[0]
+            .slices
+            .iter()
+            .flat_map(|(_, quads)| quads)
+            .copied()
+            .collect();
+        assert_eq!(quads, expected);
+    }
+}
@@ -1,36 +1,55 @@
 use std::{
-    convert::TryInto,
+    collections::HashMap,
     fmt,
     ops::{Add, Index, IndexMut},
+    sync::Arc,
 };
 
 use enum_map::Enum;
-use range_check::Check;
 use rayon::prelude::*;
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
-use crate::math::{
-    aabb::AABB,
-    axis::{Axis, ExtractAxis},
-};
+use crate::{common::direction::Direction, math::aabb::AABB};
 
 use self::{
-    block::Block,
-    chunk::{BlockSubPos, Chunk},
+    biome::Biome,
+    block::BlockId,
+    chunk::{BlockSubPos, Chunk, DirtyRegion},
+    generation::GenerationQueue,
     generator::Generator,
+    storage::EncodedChunk,
+    structure::StructureSet,
+    update::BlockChanged,
 };
 
+pub use self::generation::ChunkState;
+
+pub mod biome;
 pub mod block;
 pub mod block_iter;
 pub mod chunk;
+pub mod config;
+pub mod density;
+pub mod fluid;
+mod generation;
 pub mod generator;
-
-type SizeType = u8;
-type SizeTuple = (SizeType, SizeType);
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct ChunkPos(pub SizeType, pub SizeType);
+pub mod light;
+pub mod lod;
+pub mod mesh;
+pub mod meshing;
+mod palette;
+pub mod storage;
+pub mod structure;
+pub mod update;
+
+/// World height in blocks, chunk columns are split into sections of
+/// [`Chunk::HEIGHT`] blocks
+pub const WORLD_HEIGHT: usize = 256;
+
+/// Section position, `ChunkPos(x, y, z)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChunkPos(pub i32, pub i32, pub i32);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Enum, EnumIter)]
 pub enum ChunkNeighbor {
@@ -38,199 +57,343 @@ pub enum ChunkNeighbor {
     South,
     East,
     West,
+    Up,
+    Down,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-#[repr(transparent)]
-pub struct MapSize(SizeTuple);
-
 impl ChunkNeighbor {
-    fn as_offsets(self) -> (i32, i32) {
+    fn as_offsets(self) -> (i32, i32, i32) {
         match self {
-            ChunkNeighbor::North => (0, -1),
-            ChunkNeighbor::South => (0, 1),
-            ChunkNeighbor::East => (1, 0),
-            ChunkNeighbor::West => (-1, 0),
+            ChunkNeighbor::North => (0, 0, -1),
+            ChunkNeighbor::South => (0, 0, 1),
+            ChunkNeighbor::East => (1, 0, 0),
+            ChunkNeighbor::West => (-1, 0, 0),
+            ChunkNeighbor::Up => (0, 1, 0),
+            ChunkNeighbor::Down => (0, -1, 0),
         }
     }
 }
 
-impl MapSize {
-    #[cfg(test)]
-    pub fn new(size: SizeTuple) -> Self {
-        Self(size)
+impl From<Direction> for ChunkNeighbor {
+    fn from(direction: Direction) -> Self {
+        match direction {
+            Direction::North => ChunkNeighbor::North,
+            Direction::South => ChunkNeighbor::South,
+            Direction::East => ChunkNeighbor::East,
+            Direction::West => ChunkNeighbor::West,
+            Direction::Up => ChunkNeighbor::Up,
+            Direction::Down => ChunkNeighbor::Down,
+        }
     }
+}
 
-    pub fn width(&self) -> SizeType {
-        self.0 .0
-    }
-    pub fn height(&self) -> SizeType {
-        self.0 .1
+/// Split world position into chunk and block position
+///
+/// Returns `None` when y is outside of the world height.
+pub fn convert_pos(pos: glam::IVec3) -> Option<(ChunkPos, BlockSubPos)> {
+    let (width, height) = (Chunk::WIDTH as i32, Chunk::HEIGHT as i32);
+    let chunk_pos = ChunkPos(
+        pos.x.div_euclid(width),
+        pos.y.div_euclid(height),
+        pos.z.div_euclid(width),
+    );
+    if !chunk_pos.is_valid() {
+        return None;
     }
+    let block_pos = BlockSubPos::new(
+        pos.x.rem_euclid(width) as u8,
+        pos.y.rem_euclid(height) as u8,
+        pos.z.rem_euclid(width) as u8,
+    );
+    Some((chunk_pos, block_pos))
+}
 
-    pub fn to_world_size(&self) -> glam::UVec3 {
-        glam::uvec3(
-            (self.width() as u32) * (Chunk::WIDTH as u32),
-            Chunk::HEIGHT as u32,
-            (self.height() as u32) * (Chunk::WIDTH as u32),
-        )
+/// The six blocks sharing a face with `pos`
+pub fn block_neighbors(pos: glam::IVec3) -> impl Iterator<Item = glam::IVec3> {
+    ChunkNeighbor::iter().map(move |neighbor| {
+        let (x, y, z) = neighbor.as_offsets();
+        pos + glam::ivec3(x, y, z)
+    })
+}
+
+pub fn convert_pos_with_offset(
+    pos: glam::IVec3,
+    offset: glam::IVec3,
+) -> Option<(ChunkPos, BlockSubPos)> {
+    convert_pos(glam::ivec3(
+        pos.x.checked_add(offset.x)?,
+        pos.y.checked_add(offset.y)?,
+        pos.z.checked_add(offset.z)?,
+    ))
+}
+
+impl ChunkPos {
+    /// Number of sections in a column
+    pub const SECTIONS: i32 = (WORLD_HEIGHT / Chunk::HEIGHT) as i32;
+
+    /// Section is inside the world height
+    pub fn is_valid(self) -> bool {
+        (0..Self::SECTIONS).contains(&self.1)
     }
 
-    fn get_index(&self, ChunkPos(x, z): ChunkPos) -> usize {
-        let (w, h) = self.0;
-        x.check_range(..w).expect("x out of range");
-        z.check_range(..h).expect("z out of range");
-        let w = w as usize;
-        let (x, z) = (x as usize, z as usize);
-        z * w + x
+    /// World position of the block at (0, 0, 0)
+    pub fn origin(self) -> glam::IVec3 {
+        let (width, height) = (Chunk::WIDTH as i32, Chunk::HEIGHT as i32);
+        glam::ivec3(self.0 * width, self.1 * height, self.2 * width)
     }
 
-    fn from_index(&self, index: usize) -> ChunkPos {
-        let (w, h) = self.0;
-        let max = (w as usize) * (h as usize);
-        index.check_range(0..max).expect("index out of range");
-        let w = w as usize;
-        ChunkPos((index % w) as u8, (index / w) as u8)
+    pub fn get_neighbor(self, direction: ChunkNeighbor) -> Self {
+        let (a, b, c) = direction.as_offsets();
+        ChunkPos(self.0 + a, self.1 + b, self.2 + c)
     }
 
-    fn pos_neighbor(self, pos: ChunkPos) -> impl Iterator<Item = ChunkPos> {
-        ChunkNeighbor::iter().filter_map(move |dir| pos.get_neighbor(self, dir))
+    pub fn neighbors(self) -> impl Iterator<Item = ChunkPos> {
+        ChunkNeighbor::iter().map(move |dir| self.get_neighbor(dir))
     }
 
-    pub fn convert_pos(self, pos: glam::UVec3) -> Option<(ChunkPos, BlockSubPos)> {
-        if pos.y.check_range(0..(Chunk::HEIGHT as u32)).is_err() {
-            return None;
-        }
-        let cx = pos.x / (Chunk::WIDTH as u32);
-        let cz = pos.z / (Chunk::WIDTH as u32);
-        if cx.check_range(0..(self.width() as u32)).is_err()
-            || cz.check_range(0..(self.height() as u32)).is_err()
-        {
-            return None;
-        }
-        let (cx, cz) = (cx as u8, cz as u8);
-        let (bx, by, bz) = (
-            pos.x % (Chunk::WIDTH as u32),
-            pos.y,
-            pos.z % (Chunk::WIDTH as u32),
-        );
-        match (bx as u8, by as u8, bz as u8).try_into() {
-            Ok(block_pos) => Some((ChunkPos(cx, cz), block_pos)),
-            Err(_) => None,
+    /// Space taken by the chunk in world coordinates
+    pub fn aabb(self) -> AABB {
+        let (width, height) = (Chunk::WIDTH as f32, Chunk::HEIGHT as f32);
+        AABB {
+            position: self.origin().as_f32().into(),
+            extent3d: glam::vec3a(width, height, width),
         }
     }
 
-    pub fn convert_pos_with_offset(
-        self,
-        pos: glam::UVec3,
-        offset: glam::IVec3,
-    ) -> Option<(ChunkPos, BlockSubPos)> {
-        self.convert_pos(Axis::generate(|axis| {
-            let pos = pos.extract_axis(axis);
-            let off = offset.extract_axis(axis);
-            let dif = match (pos as i32).checked_add(off) {
-                Some(dif) => dif,
-                None => return u32::MAX,
-            };
-            match dif.try_into() {
-                Ok(val) => val,
-                Err(_) => u32::MAX,
-            }
-        }))
+    /// Chunk containing the world position
+    pub fn from_world(pos: glam::Vec3A) -> Self {
+        let (width, height) = (Chunk::WIDTH as f32, Chunk::HEIGHT as f32);
+        ChunkPos(
+            (pos.x / width).floor() as i32,
+            (pos.y / height).floor() as i32,
+            (pos.z / width).floor() as i32,
+        )
+    }
+
+    /// Chebyshev distance in chunks
+    pub fn distance(self, rhs: Self) -> i32 {
+        (self.0 - rhs.0)
+            .abs()
+            .max((self.1 - rhs.1).abs())
+            .max((self.2 - rhs.2).abs())
+    }
+
+    /// All valid chunks within `radius` (Chebyshev distance), nearest first
+    pub fn iter_range(self, radius: i32) -> impl Iterator<Item = ChunkPos> {
+        (0..=radius).flat_map(move |ring| {
+            itertools::iproduct!(-ring..=ring, -ring..=ring, -ring..=ring)
+                .filter(move |&(dx, dy, dz)| dx.abs().max(dy.abs()).max(dz.abs()) == ring)
+                .map(move |(dx, dy, dz)| ChunkPos(self.0 + dx, self.1 + dy, self.2 + dz))
+                .filter(|pos| pos.is_valid())
+        })
     }
 }
 
-impl Into<(u8, u8)> for MapSize {
-    fn into(self) -> (u8, u8) {
-        self.0
+impl fmt::Display for ChunkPos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self(x, y, z) = self;
+        write!(f, "({}, {}, {})", x, y, z)
     }
 }
 
+/// Structure blocks waiting for their chunk, see [`Map::place_structure`]
+type HeldBlocks = HashMap<ChunkPos, HashMap<BlockSubPos, BlockId>>;
+
+/// Unbounded world, chunks are generated on demand
+///
+/// Generation runs on worker threads, only chunks in [`ChunkState::Ready`]
+/// are visible through the accessors. Unloaded chunks can be kept in a
+/// compressed stash, so edits survive when the chunk is loaded again and are
+/// written out by [`Map::save`].
 pub struct Map {
-    chunks: Vec<Box<Chunk>>,
-    size: MapSize,
+    chunks: HashMap<ChunkPos, Box<Chunk>>,
+    stash: HashMap<ChunkPos, EncodedChunk>,
+    held: HeldBlocks,
+    queue: GenerationQueue,
+    restored: Vec<ChunkPos>,
+    changes: Vec<BlockChanged>,
+    seed: u32,
 }
 
-impl ChunkPos {
-    pub fn get_neighbor(self, size: MapSize, direction: ChunkNeighbor) -> Option<Self> {
-        let (a, b) = direction.as_offsets();
-        match (a, b, self.0, self.1) {
-            (-1, _, 0, _) => None,
-            (1, _, x, _) if x == size.width() - 1 => None,
-            (_, -1, _, 0) => None,
-            (_, 1, _, y) if y == size.height() - 1 => None,
-            (a, b, x, y) => Some(ChunkPos((x as i32 + a) as u8, (y as i32 + b) as u8)),
+impl Map {
+    pub fn new<G: Generator + 'static>(generator: G) -> Self {
+        Self {
+            chunks: Default::default(),
+            stash: Default::default(),
+            held: Default::default(),
+            queue: GenerationQueue::new(Arc::new(generator)),
+            restored: Vec::new(),
+            changes: Vec::new(),
+            seed: 0,
         }
     }
-}
 
-impl fmt::Display for ChunkPos {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let Self(x, z) = self;
-        write!(f, "({}, {})", x, z)
+    /// Seed the generator was built with, saved along with the world
+    pub fn with_seed(mut self, seed: u32) -> Self {
+        self.seed = seed;
+        self
     }
-}
 
-impl Map {
-    pub fn new<G: Generator>(size: SizeTuple, mut generator: G) -> Self {
-        size.0.check_range(1..).expect("size should large than 0");
-        size.1.check_range(1..).expect("size should large than 0");
-        let mut chunks = Vec::with_capacity((size.0 as usize) * (size.1 as usize));
-        generator.init(size);
-        for j in 0..size.1 {
-            for i in 0..size.0 {
-                chunks.push(generator.generate(ChunkPos(i, j)));
+    /// Structures are added to chunks generated from now on
+    pub fn with_structures(mut self, structures: StructureSet) -> Self {
+        self.queue.set_structures(structures);
+        self
+    }
+
+    pub fn is_loaded(&self, pos: ChunkPos) -> bool {
+        self.chunks.contains_key(&pos)
+    }
+
+    /// `None` if the chunk was never requested (or got unloaded)
+    pub fn state(&self, pos: ChunkPos) -> Option<ChunkState> {
+        if self.is_loaded(pos) {
+            Some(ChunkState::Ready)
+        } else {
+            self.queue.state(pos)
+        }
+    }
+
+    /// Biome of the column containing `pos`, if the generator has biomes
+    pub fn biome_at(&self, pos: glam::Vec3A) -> Option<&Biome> {
+        let (x, z) = (pos.x.floor() as i32, pos.z.floor() as i32);
+        self.queue.generator().biome_at(x, z)
+    }
+
+    pub fn get(&self, pos: ChunkPos) -> Option<&Chunk> {
+        self.chunks.get(&pos).map(|chunk| chunk.as_ref())
+    }
+
+    pub fn get_mut(&mut self, pos: ChunkPos) -> Option<&mut Chunk> {
+        self.chunks.get_mut(&pos).map(|chunk| chunk.as_mut())
+    }
+
+    /// Block at world position, `None` when the chunk isn't loaded
+    pub fn block_at(&self, pos: glam::IVec3) -> Option<Option<BlockId>> {
+        let (chunk_pos, sub_pos) = convert_pos(pos)?;
+        Some(self.get(chunk_pos)?[sub_pos])
+    }
+
+    /// Mark the sides of the neighbors facing `pos` for remeshing
+    fn mark_neighbor_faces_dirty(&mut self, pos: ChunkPos) {
+        for direction in Direction::iter() {
+            if let Some(chunk) = self.chunks.get_mut(&pos.get_neighbor(direction.into())) {
+                chunk.mark_region_dirty(DirtyRegion::face(direction.opposite()));
             }
         }
-        let size = MapSize(size);
-        Self { chunks, size }
     }
 
-    pub fn size(&self) -> MapSize {
-        self.size
+    fn insert_chunk(&mut self, pos: ChunkPos, mut chunk: Box<Chunk>) {
+        self.place_held(pos, &mut chunk);
+        self.chunks.insert(pos, chunk);
+        // neighbors may have faces hidden by the new chunk
+        self.mark_neighbor_faces_dirty(pos);
+        log::debug!("load chunk@{}", pos);
+    }
+
+    /// Write the structure rooted in a freshly generated chunk, then insert
+    /// it
+    fn insert_generated(&mut self, pos: ChunkPos, (chunk, structure): generation::Generated) {
+        if let Some(placement) = structure {
+            self.place_structure(placement);
+        }
+        self.insert_chunk(pos, chunk);
     }
 
-    pub fn in_range(&self, pos: ChunkPos) -> bool {
-        let (w, h) = self.size.into();
-        pos.0.check_range(..w).is_ok() && pos.1.check_range(..h).is_ok()
+    /// Load chunk from stash or generate it on the current thread
+    pub fn load_chunk(&mut self, pos: ChunkPos) -> &mut Chunk {
+        if !self.chunks.contains_key(&pos) {
+            self.queue.cancel(pos);
+            match self.stash.remove(&pos) {
+                Some(encoded) => self.insert_chunk(pos, encoded.decode()),
+                None => {
+                    let generated = self.queue.generate(pos);
+                    self.insert_generated(pos, generated);
+                }
+            }
+        }
+        self.chunks.get_mut(&pos).unwrap()
+    }
+
+    /// Load chunk from stash or queue it for background generation
+    pub fn request_chunk(&mut self, pos: ChunkPos) {
+        if !pos.is_valid() || self.chunks.contains_key(&pos) {
+            return;
+        }
+        match self.stash.remove(&pos) {
+            Some(encoded) => {
+                self.insert_chunk(pos, encoded.decode());
+                self.restored.push(pos);
+            }
+            None => self.queue.request(pos),
+        }
+    }
+
+    /// Collect chunks finished by the workers, returns positions of all
+    /// chunks that became ready since the last call
+    pub fn poll_generation(&mut self) -> Vec<ChunkPos> {
+        let mut ready = std::mem::take(&mut self.restored);
+        for (pos, generated) in self.queue.poll() {
+            self.insert_generated(pos, generated);
+            ready.push(pos);
+        }
+        ready
+    }
+
+    /// Unload chunk (or cancel its generation), keep it in stash if `keep` is set
+    pub fn unload_chunk(&mut self, pos: ChunkPos, keep: bool) -> bool {
+        let chunk = match self.chunks.remove(&pos) {
+            Some(chunk) => chunk,
+            None => return self.queue.cancel(pos),
+        };
+        if keep {
+            self.stash.insert(pos, EncodedChunk::encode(&chunk));
+            // the stashed chunk has them
+            self.held.remove(&pos);
+        }
+        // neighbors have to show faces facing the unloaded chunk
+        self.mark_neighbor_faces_dirty(pos);
+        log::debug!("unload chunk@{}", pos);
+        true
+    }
+
+    /// Ready and requested chunks
+    pub fn loaded(&self) -> impl '_ + Iterator<Item = ChunkPos> {
+        self.chunks.keys().copied().chain(self.queue.requested())
     }
 
     pub fn par_iter(&self) -> impl ParallelIterator<Item = (ChunkPos, &Chunk)> {
-        let size = self.size;
-        self.chunks
-            .par_iter()
-            .enumerate()
-            .map(move |(i, c)| (size.from_index(i), c.as_ref()))
+        self.chunks.par_iter().map(|(&pos, c)| (pos, c.as_ref()))
     }
 
     pub fn iter(&self) -> impl Iterator<Item = (ChunkPos, &Chunk)> {
-        let size = self.size;
-        self.chunks
-            .iter()
-            .enumerate()
-            .map(move |(i, c)| (size.from_index(i), c.as_ref()))
+        self.chunks.iter().map(|(&pos, c)| (pos, c.as_ref()))
     }
 
     pub fn iter_neighbor(&self, pos: ChunkPos) -> impl Iterator<Item = (ChunkPos, &Chunk)> {
-        self.size
-            .pos_neighbor(pos)
-            .map(move |pos| (pos, &self[pos]))
+        pos.neighbors()
+            .filter_map(move |pos| self.get(pos).map(|chunk| (pos, chunk)))
     }
 
     pub fn iter_mut(&mut self) -> impl Iterator<Item = (ChunkPos, &mut Chunk)> {
-        let size = self.size;
-        self.chunks
-            .iter_mut()
-            .enumerate()
-            .map(move |(i, c)| (size.from_index(i), c.as_mut()))
+        self.chunks.iter_mut().map(|(&pos, c)| (pos, c.as_mut()))
     }
 
-    pub fn scan_aabb(&self, aabb: AABB) -> impl Iterator<Item = (glam::Vec3A, &Block)> {
-        let size = self.size;
+    /// Non-air blocks overlapping the aabb
+    ///
+    /// Blocks in chunks that are not ready yet are reported as `None`, callers
+    /// should treat them as solid.
+    pub fn scan_aabb(
+        &self,
+        aabb: AABB,
+    ) -> impl Iterator<Item = (glam::Vec3A, Option<BlockId>)> + '_ {
         aabb.iter_pos()
-            .filter_map(move |pos| size.convert_pos(pos))
+            .filter_map(convert_pos)
             .filter_map(move |(chunk_pos, block_pos)| {
-                self[chunk_pos][block_pos].map(move |x| (chunk_pos + block_pos, x))
+                let pos = chunk_pos + block_pos;
+                match self.get(chunk_pos) {
+                    Some(chunk) => chunk[block_pos].map(|blk| (pos, Some(blk))),
+                    None => Some((pos, None)),
+                }
             })
     }
 }
@@ -240,10 +403,13 @@ impl Add<BlockSubPos> for ChunkPos {
 
     fn add(self, rhs: BlockSubPos) -> Self::Output {
         let (x, y, z) = rhs.into();
-        let ChunkPos(mx, mz) = self;
-        let (x, y, z, mx, mz) = (x as f32, y as f32, z as f32, mx as f32, mz as f32);
-        let w = Chunk::WIDTH as f32;
-        glam::vec3a(mx * w + x, y, mz * w + z)
+        let ChunkPos(mx, my, mz) = self;
+        let (w, h) = (Chunk::WIDTH as f32, Chunk::HEIGHT as f32);
+        glam::vec3a(
+            mx as f32 * w + x as f32,
+            my as f32 * h + y as f32,
+            mz as f32 * w + z as f32,
+        )
     }
 }
 
@@ -251,13 +417,82 @@ impl Index<ChunkPos> for Map {
     type Output = Chunk;
 
     fn index(&self, index: ChunkPos) -> &Self::Output {
-        &self.chunks[self.size.get_index(index)]
+        self.get(index).expect("chunk not loaded")
     }
 }
 
 impl IndexMut<ChunkPos> for Map {
     fn index_mut(&mut self, index: ChunkPos) -> &mut Self::Output {
-        let idx = self.size.get_index(index);
-        &mut self.chunks[idx]
+        self.get_mut(index).expect("chunk not loaded")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_pos() {
+        assert_eq!(
+            convert_pos(glam::ivec3(17, 3, -1)),
+            Some((ChunkPos(1, 0, -1), BlockSubPos::new(1, 3, 15)))
+        );
+        assert_eq!(
+            convert_pos(glam::ivec3(-16, 0, 0)),
+            Some((ChunkPos(-1, 0, 0), BlockSubPos::new(0, 0, 0)))
+        );
+        assert_eq!(
+            convert_pos(glam::ivec3(0, 200, 0)),
+            Some((ChunkPos(0, 12, 0), BlockSubPos::new(0, 8, 0)))
+        );
+        assert_eq!(convert_pos(glam::ivec3(0, -1, 0)), None);
+        assert_eq!(convert_pos(glam::ivec3(0, WORLD_HEIGHT as i32, 0)), None);
+        assert_eq!(
+            ChunkPos::from_world(glam::vec3a(-0.5, 17.0, 16.0)),
+            ChunkPos(-1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn test_background_generation() {
+        use generator::flat::{FlatGenerator, Span};
+        let registry = block::test_registry();
+        let blk = registry.lookup("red").ok();
+        let mut map = Map::new(FlatGenerator::new(&[Span(blk, 3)]));
+        let (pos, cancelled) = (ChunkPos(-2, 0, 5), ChunkPos(7, 1, 7));
+        map.request_chunk(pos);
+        map.request_chunk(cancelled);
+        assert_eq!(map.state(pos), Some(ChunkState::Pending));
+        assert!(map.get(pos).is_none());
+        assert!(map.unload_chunk(cancelled, false));
+        assert_eq!(map.state(cancelled), None);
+
+        let mut ready = Vec::new();
+        for _ in 0..1000 {
+            ready.extend(map.poll_generation());
+            if !ready.is_empty() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(ready, vec![pos]);
+        assert_eq!(map.state(pos), Some(ChunkState::Ready));
+        assert_eq!(map[pos][BlockSubPos::new(0, 2, 0)], blk);
+        assert!(map.poll_generation().is_empty());
+        assert!(!map.is_loaded(cancelled));
+    }
+
+    #[test]
+    fn test_iter_range() {
+        let center = ChunkPos(3, 4, -2);
+        let range: Vec<_> = center.iter_range(2).collect();
+        assert_eq!(range.len(), 125);
+        assert_eq!(range[0], center);
+        assert!(range.iter().all(|pos| pos.distance(center) <= 2));
+        assert!(range
+            .windows(2)
+            .all(|w| w[0].distance(center) <= w[1].distance(center)));
+        // sections outside the world height are skipped
+        assert_eq!(ChunkPos(0, 0, 0).iter_range(1).count(), 18);
     }
 }
@@ -0,0 +1,256 @@
+use super::block::BlockId;
+
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// Palette + bit-packed index storage
+///
+/// A storage with a single palette entry doesn't allocate index data at all,
+/// which keeps chunks filled with air (or one block) tiny. Index width is
+/// always a power of two so an index never straddles two words. Every entry
+/// counts its uses, so a storage filled with one value again goes back to
+/// the single entry form. Blocks are stored by default, light and fluid
+/// levels use the same storage.
+#[derive(Debug, Clone)]
+pub struct PalettedArray<T = Option<BlockId>> {
+    len: usize,
+    bits: usize,
+    palette: Vec<T>,
+    counts: Vec<usize>,
+    data: Vec<u64>,
+}
+
+fn bits_for(count: usize) -> usize {
+    match count {
+        0 | 1 => 0,
+        count => (usize::BITS - (count - 1).leading_zeros())
+            .next_power_of_two()
+            .min(16) as usize,
+    }
+}
+
+impl<T: Copy + PartialEq + Default> PalettedArray<T> {
+    pub fn filled(len: usize, value: T) -> Self {
+        Self {
+            len,
+            bits: 0,
+            palette: vec![value],
+            counts: vec![len],
+            data: Vec::new(),
+        }
+    }
+
+    pub fn from_slice(values: &[T]) -> Self {
+        let mut palette: Vec<T> = Vec::new();
+        let mut counts = Vec::new();
+        let indices: Vec<usize> = values
+            .iter()
+            .map(|value| {
+                let idx = match palette.iter().position(|x| x == value) {
+                    Some(idx) => idx,
+                    None => {
+                        palette.push(*value);
+                        counts.push(0);
+                        palette.len() - 1
+                    }
+                };
+                counts[idx] += 1;
+                idx
+            })
+            .collect();
+        if palette.is_empty() {
+            palette.push(T::default());
+            counts.push(0);
+        }
+        let mut ret = Self {
+            len: values.len(),
+            bits: bits_for(palette.len()),
+            palette,
+            counts,
+            data: Vec::new(),
+        };
+        ret.data = ret.pack(indices.into_iter());
+        ret
+    }
+
+    pub fn is_uniform(&self) -> bool {
+        self.bits == 0
+    }
+
+    /// Approximate heap usage in bytes
+    pub fn heap_size(&self) -> usize {
+        self.palette.capacity() * std::mem::size_of::<T>()
+            + self.counts.capacity() * std::mem::size_of::<usize>()
+            + self.data.capacity() * std::mem::size_of::<u64>()
+    }
+
+    #[inline(always)]
+    fn per_word(&self) -> usize {
+        WORD_BITS / self.bits
+    }
+
+    #[inline(always)]
+    fn mask(&self) -> u64 {
+        (1u64 << self.bits) - 1
+    }
+
+    #[inline(always)]
+    fn index_of(&self, index: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        let per_word = self.per_word();
+        let word = self.data[index / per_word];
+        let shift = (index % per_word) * self.bits;
+        ((word >> shift) & self.mask()) as usize
+    }
+
+    fn write_index(&mut self, index: usize, value: usize) {
+        let per_word = self.per_word();
+        let shift = (index % per_word) * self.bits;
+        let mask = self.mask() << shift;
+        let word = &mut self.data[index / per_word];
+        *word = (*word & !mask) | ((value as u64) << shift);
+    }
+
+    fn pack(&self, indices: impl Iterator<Item = usize>) -> Vec<u64> {
+        if self.bits == 0 {
+            return Vec::new();
+        }
+        let per_word = self.per_word();
+        let mut data = vec![0u64; self.len.saturating_sub(1) / per_word + 1];
+        for (i, value) in indices.enumerate() {
+            data[i / per_word] |= (value as u64) << ((i % per_word) * self.bits);
+        }
+        data
+    }
+
+    pub fn get(&self, index: usize) -> &T {
+        assert!(index < self.len, "index out of range");
+        &self.palette[self.index_of(index)]
+    }
+
+    /// Sequential access, decodes whole words instead of single indices
+    pub fn iter(&self) -> impl '_ + Iterator<Item = &'_ T> {
+        let bits = self.bits.max(1);
+        let per_word = WORD_BITS / bits;
+        let mask = self.mask();
+        let uniform = self.bits == 0;
+        (0..self.len).map(move |i| {
+            if uniform {
+                &self.palette[0]
+            } else {
+                let word = self.data[i / per_word];
+                &self.palette[((word >> ((i % per_word) * bits)) & mask) as usize]
+            }
+        })
+    }
+
+    /// Drop unused palette entries
+    fn compact(&mut self) {
+        if self.counts.iter().all(|&count| count > 0) {
+            return;
+        }
+        let mut remap = vec![0usize; self.palette.len()];
+        let mut palette = Vec::new();
+        let mut counts = Vec::new();
+        for (old, value) in self.palette.iter().enumerate() {
+            if self.counts[old] > 0 {
+                remap[old] = palette.len();
+                palette.push(*value);
+                counts.push(self.counts[old]);
+            }
+        }
+        let indices: Vec<usize> = (0..self.len).map(|i| remap[self.index_of(i)]).collect();
+        self.palette = palette;
+        self.counts = counts;
+        self.bits = bits_for(self.palette.len());
+        self.data = self.pack(indices.into_iter());
+    }
+
+    fn resize(&mut self, bits: usize) {
+        let indices: Vec<usize> = (0..self.len).map(|i| self.index_of(i)).collect();
+        self.bits = bits;
+        self.data = self.pack(indices.into_iter());
+    }
+
+    fn palette_index(&mut self, value: T) -> usize {
+        if let Some(idx) = self.palette.iter().position(|x| *x == value) {
+            return idx;
+        }
+        if self.palette.len() >= (1 << self.bits) {
+            self.compact();
+        }
+        if self.palette.len() >= (1 << self.bits) {
+            self.resize(bits_for(self.palette.len() + 1));
+        }
+        self.palette.push(value);
+        self.counts.push(0);
+        self.palette.len() - 1
+    }
+
+    /// Replace value at index, returns the previous value
+    pub fn set(&mut self, index: usize, value: T) -> T {
+        assert!(index < self.len, "index out of range");
+        let old_idx = self.index_of(index);
+        let old = self.palette[old_idx];
+        if old == value {
+            return old;
+        }
+        self.counts[old_idx] -= 1;
+        let idx = self.palette_index(value);
+        self.counts[idx] += 1;
+        if self.counts[idx] == self.len {
+            *self = Self::filled(self.len, value);
+        } else {
+            self.write_index(index, idx);
+        }
+        old
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::block::test_registry;
+
+    #[test]
+    fn test_set_get() {
+        let registry = test_registry();
+        let ids: Vec<_> = registry.iter().map(|(id, _)| Some(id)).collect();
+        let mut arr = PalettedArray::filled(1000, None);
+        assert!(arr.is_uniform());
+        assert_eq!(
+            arr.heap_size(),
+            std::mem::size_of::<Option<BlockId>>() + std::mem::size_of::<usize>()
+        );
+        for i in 0..1000 {
+            arr.set(i, ids[i % ids.len()]);
+        }
+        assert!(!arr.is_uniform());
+        for i in 0..1000 {
+            assert_eq!(*arr.get(i), ids[i % ids.len()]);
+        }
+        for i in 0..1000 {
+            arr.set(i, ids[0]);
+        }
+        assert!(arr.is_uniform());
+        arr.set(3, None);
+        assert_eq!(*arr.get(3), None);
+        assert_eq!(*arr.get(4), ids[0]);
+    }
+
+    #[test]
+    fn test_from_slice() {
+        let registry = test_registry();
+        let id = registry.lookup("red").ok();
+        let values: Vec<_> = (0..100)
+            .map(|i| if i % 3 == 0 { id } else { None })
+            .collect();
+        let arr = PalettedArray::from_slice(&values);
+        for (i, value) in values.iter().enumerate() {
+            assert_eq!(arr.get(i), value);
+        }
+        assert!(arr.iter().eq(values.iter()));
+        assert!(PalettedArray::from_slice(&[id; 10]).is_uniform());
+    }
+}
@@ -0,0 +1,578 @@
+//! Binary world format
+//!
+//! Layout (all integers are little endian):
+//!
+//! ```text
+//! magic    b"SBXW"
+//! version  u16
+//! seed     u32
+//! palette  u16 (count), count * (u8 (length), utf-8 name)
+//! chunks   u32 (count), count * (i32 (x), i32 (y), i32 (z), chunk)
+//! held     u32 (count), count * (i32 (x), i32 (y), i32 (z), blocks)
+//! ```
+//!
+//! Every chunk starts with a tag byte, `0` for a chunk filled with a single
+//! palette entry (followed by the u16 entry) and `1` for run-length encoded
+//! data (u32 run count, then runs of u16 entry + u16 length). Palette entry
+//! `0` is always air, block names start from `1` and are resolved through the
+//! [`BlockRegistry`] when loading. Block data is followed by the fluid levels
+//! that aren't sources: u16 count, then u8 x, y, z and level each.
+//!
+//! Held structure blocks are kept for chunks that were never saved, as u16
+//! count, then u8 x, y, z and u16 palette entry each.
+
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use anyhow::{bail, ensure, Context};
+
+use super::{
+    block::{BlockId, BlockRegistry},
+    chunk::{BlockSubPos, Chunk, CHUNK_SIZE},
+    fluid::FluidLevel,
+    generator::Generator,
+    ChunkPos, HeldBlocks, Map,
+};
+
+const MAGIC: &[u8; 4] = b"SBXW";
+pub const VERSION: u16 = 6;
+
+const TAG_UNIFORM: u8 = 0;
+const TAG_RUN_LENGTH: u8 = 1;
+
+type PaletteEntry = u16;
+
+fn write_u8(writer: &mut impl Write, value: u8) -> anyhow::Result<()> {
+    writer.write_all(&[value])?;
+    Ok(())
+}
+
+fn write_u16(writer: &mut impl Write, value: u16) -> anyhow::Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_u32(writer: &mut impl Write, value: u32) -> anyhow::Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_i32(writer: &mut impl Write, value: i32) -> anyhow::Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn read_u8(reader: &mut impl Read) -> anyhow::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16(reader: &mut impl Read) -> anyhow::Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32(reader: &mut impl Read) -> anyhow::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_i32(reader: &mut impl Read) -> anyhow::Result<i32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+/// Run-length encoded chunk, used for unloaded chunks and world files
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodedChunk {
+    runs: Vec<(Option<BlockId>, u16)>,
+    fluid: Vec<(BlockSubPos, FluidLevel)>,
+}
+
+impl EncodedChunk {
+    pub fn encode(chunk: &Chunk) -> Self {
+        let mut runs: Vec<(Option<BlockId>, u16)> = Vec::new();
+        for (_, &blk) in chunk.iter() {
+            match runs.last_mut() {
+                Some((last, length)) if *last == blk && *length < u16::MAX => *length += 1,
+                _ => runs.push((blk, 1)),
+            }
+        }
+        Self {
+            runs,
+            fluid: chunk.iter_fluid().collect(),
+        }
+    }
+
+    pub fn decode(&self) -> Box<Chunk> {
+        let mut chunk = match self.runs[..] {
+            [(blk, _)] => Chunk::filled(blk),
+            _ => {
+                let mut blocks = Vec::with_capacity(CHUNK_SIZE);
+                for &(blk, length) in &self.runs {
+                    blocks.resize(blocks.len() + length as usize, blk);
+                }
+                Chunk::from_slice(&blocks)
+            }
+        };
+        for &(pos, level) in &self.fluid {
+            chunk.set_fluid(pos, level);
+        }
+        chunk
+    }
+}
+
+#[derive(Default)]
+struct PaletteBuilder {
+    blocks: Vec<BlockId>,
+    lookup: HashMap<BlockId, PaletteEntry>,
+}
+
+impl PaletteBuilder {
+    fn entry(&mut self, block: Option<BlockId>) -> PaletteEntry {
+        let block = match block {
+            Some(block) => block,
+            None => return 0,
+        };
+        let blocks = &mut self.blocks;
+        *self.lookup.entry(block).or_insert_with(|| {
+            blocks.push(block);
+            blocks.len() as PaletteEntry
+        })
+    }
+}
+
+fn write_chunk(
+    writer: &mut impl Write,
+    chunk: &EncodedChunk,
+    palette: &mut PaletteBuilder,
+) -> anyhow::Result<()> {
+    match chunk.runs[..] {
+        [(blk, _)] => {
+            write_u8(writer, TAG_UNIFORM)?;
+            write_u16(writer, palette.entry(blk))?;
+        }
+        _ => {
+            write_u8(writer, TAG_RUN_LENGTH)?;
+            write_u32(writer, chunk.runs.len() as u32)?;
+            for &(blk, length) in &chunk.runs {
+                write_u16(writer, palette.entry(blk))?;
+                write_u16(writer, length)?;
+            }
+        }
+    }
+    write_u16(writer, chunk.fluid.len() as u16)?;
+    for &(pos, level) in &chunk.fluid {
+        let (x, y, z) = pos.into();
+        writer.write_all(&[x, y, z, level.raw()])?;
+    }
+    Ok(())
+}
+
+fn read_fluid(reader: &mut impl Read) -> anyhow::Result<Vec<(BlockSubPos, FluidLevel)>> {
+    let count = read_u16(reader)?;
+    let mut fluid = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf)?;
+        let [x, y, z, level] = buf;
+        let pos = BlockSubPos::try_from((x, y, z))
+            .ok()
+            .with_context(|| format!("fluid position ({}, {}, {}) out of range", x, y, z))?;
+        fluid.push((pos, FluidLevel::from_raw(level)));
+    }
+    Ok(fluid)
+}
+
+fn write_held(
+    writer: &mut impl Write,
+    blocks: &HashMap<BlockSubPos, BlockId>,
+    palette: &mut PaletteBuilder,
+) -> anyhow::Result<()> {
+    let mut blocks: Vec<_> = blocks.iter().collect();
+    blocks.sort();
+    write_u16(writer, blocks.len() as u16)?;
+    for (&pos, &block) in blocks {
+        let (x, y, z) = pos.into();
+        writer.write_all(&[x, y, z])?;
+        write_u16(writer, palette.entry(Some(block)))?;
+    }
+    Ok(())
+}
+
+fn read_held(
+    reader: &mut impl Read,
+    palette: &[Option<BlockId>],
+) -> anyhow::Result<HashMap<BlockSubPos, BlockId>> {
+    let count = read_u16(reader)?;
+    let mut blocks = HashMap::with_capacity(count as usize);
+    for _ in 0..count {
+        let mut buf = [0u8; 3];
+        reader.read_exact(&mut buf)?;
+        let [x, y, z] = buf;
+        let pos = BlockSubPos::try_from((x, y, z))
+            .ok()
+            .with_context(|| format!("held position ({}, {}, {}) out of range", x, y, z))?;
+        let entry = read_u16(reader)?;
+        let block = palette
+            .get(entry as usize)
+            .copied()
+            .flatten()
+            .with_context(|| format!("invalid held palette entry {}", entry))?;
+        blocks.insert(pos, block);
+    }
+    Ok(blocks)
+}
+
+fn read_chunk(reader: &mut impl Read, palette: &[Option<BlockId>]) -> anyhow::Result<EncodedChunk> {
+    let resolve = |entry: PaletteEntry| -> anyhow::Result<Option<BlockId>> {
+        palette
+            .get(entry as usize)
+            .copied()
+            .with_context(|| format!("palette entry {} out of range", entry))
+    };
+    let runs = match read_u8(reader)? {
+        TAG_UNIFORM => {
+            let blk = resolve(read_u16(reader)?)?;
+            vec![(blk, CHUNK_SIZE as u16)]
+        }
+        TAG_RUN_LENGTH => {
+            let count = read_u32(reader)?;
+            let mut runs = Vec::new();
+            let mut total = 0usize;
+            for _ in 0..count {
+                let blk = resolve(read_u16(reader)?)?;
+                let length = read_u16(reader)?;
+                total += length as usize;
+                ensure!(
+                    total <= CHUNK_SIZE,
+                    "chunk data overflow ({} > {})",
+                    total,
+                    CHUNK_SIZE
+                );
+                runs.push((blk, length));
+            }
+            ensure!(
+                total == CHUNK_SIZE,
+                "chunk data size mismatch ({} != {})",
+                total,
+                CHUNK_SIZE
+            );
+            runs
+        }
+        tag => bail!("unknown chunk tag {}", tag),
+    };
+    Ok(EncodedChunk {
+        runs,
+        fluid: read_fluid(reader)?,
+    })
+}
+
+impl Map {
+    /// Save loaded and stashed chunks
+    pub fn save(&self, path: impl AsRef<Path>, registry: &BlockRegistry) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .with_context(|| format!("failed to create world file {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        self.write_to(&mut writer, registry)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn write_to(&self, writer: &mut impl Write, registry: &BlockRegistry) -> anyhow::Result<()> {
+        let mut chunks: Vec<(ChunkPos, EncodedChunk)> = self
+            .iter()
+            .map(|(pos, chunk)| (pos, EncodedChunk::encode(chunk)))
+            .chain(self.stash.iter().map(|(&pos, chunk)| (pos, chunk.clone())))
+            .collect();
+        chunks.sort_by_key(|(pos, _)| *pos);
+
+        // palette goes before chunk data, so encode chunks first
+        let mut palette = PaletteBuilder::default();
+        let mut body = Vec::new();
+        write_u32(&mut body, chunks.len() as u32)?;
+        for (ChunkPos(x, y, z), chunk) in &chunks {
+            write_i32(&mut body, *x)?;
+            write_i32(&mut body, *y)?;
+            write_i32(&mut body, *z)?;
+            write_chunk(&mut body, chunk, &mut palette)?;
+        }
+        // saved chunks already contain their structure blocks
+        let mut held: Vec<_> = self
+            .held
+            .iter()
+            .filter(|(pos, _)| !self.is_loaded(**pos) && !self.stash.contains_key(pos))
+            .collect();
+        held.sort_by_key(|(pos, _)| **pos);
+        write_u32(&mut body, held.len() as u32)?;
+        for (ChunkPos(x, y, z), blocks) in held {
+            write_i32(&mut body, *x)?;
+            write_i32(&mut body, *y)?;
+            write_i32(&mut body, *z)?;
+            write_held(&mut body, blocks, &mut palette)?;
+        }
+
+        writer.write_all(MAGIC)?;
+        write_u16(writer, VERSION)?;
+        write_u32(writer, self.seed)?;
+        write_u16(writer, palette.blocks.len() as u16)?;
+        for &block in &palette.blocks {
+            let name = &registry[block].name;
+            let bytes = name.as_bytes();
+            ensure!(
+                bytes.len() <= u8::MAX as usize,
+                "block name too long: {}",
+                name
+            );
+            write_u8(writer, bytes.len() as u8)?;
+            writer.write_all(bytes)?;
+        }
+        writer.write_all(&body)?;
+        Ok(())
+    }
+
+    /// Load world file, saved chunks are used instead of generating them
+    ///
+    /// Fails when the world was saved with another seed, the saved chunks
+    /// wouldn't match the terrain `generator` creates around them.
+    pub fn load<G: Generator + 'static>(
+        path: impl AsRef<Path>,
+        registry: &BlockRegistry,
+        generator: G,
+        seed: u32,
+    ) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("failed to open world file {}", path.display()))?;
+        let mut map = Self::new(generator).with_seed(seed);
+        let (stash, held) = map
+            .read_from(&mut BufReader::new(file), registry)
+            .with_context(|| format!("failed to load world file {}", path.display()))?;
+        map.stash = stash;
+        map.held = held;
+        Ok(map)
+    }
+
+    fn read_from(
+        &self,
+        reader: &mut impl Read,
+        registry: &BlockRegistry,
+    ) -> anyhow::Result<(HashMap<ChunkPos, EncodedChunk>, HeldBlocks)> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        ensure!(&magic == MAGIC, "not a world file");
+        let version = read_u16(reader)?;
+        ensure!(
+            version == VERSION,
+            "unsupported world version {} (expected {})",
+            version,
+            VERSION
+        );
+        let seed = read_u32(reader)?;
+        ensure!(
+            seed == self.seed,
+            "world was saved with seed {} (expected {})",
+            seed,
+            self.seed
+        );
+
+        let count = read_u16(reader)?;
+        let mut palette = Vec::with_capacity(count as usize + 1);
+        palette.push(None);
+        for _ in 0..count {
+            let mut name = vec![0u8; read_u8(reader)? as usize];
+            reader.read_exact(&mut name)?;
+            let name = String::from_utf8(name).context("invalid block name")?;
+            palette.push(Some(registry.lookup(&name)?));
+        }
+
+        let chunk_count = read_u32(reader)?;
+        let mut chunks = HashMap::new();
+        for i in 0..chunk_count {
+            let pos = ChunkPos(read_i32(reader)?, read_i32(reader)?, read_i32(reader)?);
+            ensure!(pos.is_valid(), "chunk {} outside of world height", pos);
+            let chunk = read_chunk(reader, &palette)
+                .with_context(|| format!("failed to read chunk {} of {}", i, chunk_count))?;
+            ensure!(
+                chunks.insert(pos, chunk).is_none(),
+                "duplicated chunk {}",
+                pos
+            );
+        }
+        let held_count = read_u32(reader)?;
+        let mut held = HeldBlocks::new();
+        for _ in 0..held_count {
+            let pos = ChunkPos(read_i32(reader)?, read_i32(reader)?, read_i32(reader)?);
+            ensure!(
+                pos.is_valid(),
+                "held blocks {} outside of world height",
+                pos
+            );
+            let blocks = read_held(reader, &palette)
+                .with_context(|| format!("failed to read held blocks for {}", pos))?;
+            ensure!(
+                held.insert(pos, blocks).is_none(),
+                "duplicated held blocks {}",
+                pos
+            );
+        }
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest)?;
+        ensure!(
+            rest.is_empty(),
+            "chunk count mismatch: {} trailing bytes",
+            rest.len()
+        );
+        Ok((chunks, held))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{
+        block::test_registry,
+        chunk::BlockSubPos,
+        generator::flat::{FlatGenerator, Span},
+    };
+
+    const SEED: u32 = 1234;
+
+    fn generator(registry: &BlockRegistry) -> FlatGenerator {
+        let block = |name| Some(registry.lookup(name).unwrap());
+        FlatGenerator::new(&[
+            Span(block("blue"), 2),
+            Span(block("green"), 3),
+            Span(None, 1),
+            Span(block("red"), 1),
+        ])
+    }
+
+    fn sample_map(registry: &BlockRegistry) -> Map {
+        let block = |name| Some(registry.lookup(name).unwrap());
+        let mut map = empty_map(registry);
+        for pos in ChunkPos(0, 0, 0).iter_range(1) {
+            map.load_chunk(pos);
+        }
+        map[ChunkPos(1, 0, -1)][BlockSubPos::new(3, 10, 4)] = block("yellow");
+        map[ChunkPos(0, 1, 1)][BlockSubPos::new(0, 0, 0)] = block("purple");
+        let water = BlockSubPos::new(5, 12, 5);
+        map[ChunkPos(1, 0, -1)].set(water, block("water"));
+        map[ChunkPos(1, 0, -1)].set_fluid(water, FluidLevel::flowing(3));
+        map.unload_chunk(ChunkPos(1, 0, -1), true);
+        map.unload_chunk(ChunkPos(-1, 0, -1), false);
+        // only blocks held for chunks that aren't saved are written
+        for &pos in &[ChunkPos(3, 0, 0), ChunkPos(0, 0, 0)] {
+            map.held
+                .entry(pos)
+                .or_default()
+                .insert(BlockSubPos::new(1, 2, 3), block("yellow").unwrap());
+        }
+        map
+    }
+
+    /// Empty map to load into
+    fn empty_map(registry: &BlockRegistry) -> Map {
+        Map::new(generator(registry)).with_seed(SEED)
+    }
+
+    fn blocks(chunk: &Chunk) -> Vec<Option<BlockId>> {
+        chunk.iter().map(|(_, &blk)| blk).collect()
+    }
+
+    fn write_to_vec(map: &Map, registry: &BlockRegistry) -> Vec<u8> {
+        let mut data = Vec::new();
+        map.write_to(&mut data, registry).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let registry = test_registry();
+        let map = sample_map(&registry);
+        let data = write_to_vec(&map, &registry);
+        let mut loaded = empty_map(&registry);
+        let (stash, held) = loaded.read_from(&mut &data[..], &registry).unwrap();
+        loaded.stash = stash;
+        loaded.held = held;
+        assert_eq!(loaded.stash.len(), 17);
+        assert_eq!(loaded.held.len(), 1);
+        assert_eq!(write_to_vec(&loaded, &registry), data);
+
+        let water = loaded.load_chunk(ChunkPos(1, 0, -1));
+        assert_eq!(
+            water.fluid(BlockSubPos::new(5, 12, 5)),
+            FluidLevel::flowing(3)
+        );
+
+        let mut expected = sample_map(&registry);
+        // dropped chunk (-1, 0, -1) is generated again on both sides
+        for pos in ChunkPos(0, 0, 0)
+            .iter_range(1)
+            .chain(std::iter::once(ChunkPos(3, 0, 0)))
+        {
+            assert_eq!(
+                blocks(loaded.load_chunk(pos)),
+                blocks(expected.load_chunk(pos)),
+                "chunk {} mismatch",
+                pos
+            );
+        }
+    }
+
+    #[test]
+    fn test_reject_version() {
+        let registry = test_registry();
+        let mut data = write_to_vec(&sample_map(&registry), &registry);
+        data[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        let err = empty_map(&registry)
+            .read_from(&mut &data[..], &registry)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("version"));
+    }
+
+    #[test]
+    fn test_reject_seed() {
+        let registry = test_registry();
+        let data = write_to_vec(&sample_map(&registry), &registry);
+        let other = Map::new(generator(&registry)).with_seed(SEED + 1);
+        let err = other.read_from(&mut &data[..], &registry).err().unwrap();
+        assert!(err.to_string().contains("seed"));
+    }
+
+    #[test]
+    fn test_reject_size() {
+        let registry = test_registry();
+        let mut data = write_to_vec(&sample_map(&registry), &registry);
+        let map = empty_map(&registry);
+        assert!(map
+            .read_from(&mut &data[..data.len() - 1], &registry)
+            .is_err());
+        data.push(0);
+        assert!(map.read_from(&mut &data[..], &registry).is_err());
+    }
+
+    #[test]
+    fn test_reject_unknown_block() {
+        let registry = test_registry();
+        let data = write_to_vec(&sample_map(&registry), &registry);
+        let other = BlockRegistry::from_ron(r#"[(name: "blue", data: Solid(color: (0, 0, 255)))]"#)
+            .unwrap();
+        let err = empty_map(&registry)
+            .read_from(&mut &data[..], &other)
+            .err()
+            .unwrap();
+        assert!(format!("{:?}", err).contains("unknown block name"));
+    }
+}
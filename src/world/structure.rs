@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, ensure};
+use glam::IVec3;
+use serde::Deserialize;
+
+use super::{
+    biome::column_hash,
+    block::{BlockId, BlockRegistry},
+    chunk::Chunk,
+    convert_pos,
+    generator::Generator,
+    ChunkPos, Map,
+};
+
+/// Structure template as written in the world config
+#[derive(Debug, Clone, Deserialize)]
+pub struct StructureConfig {
+    pub name: String,
+    /// Probability per chunk column
+    pub chance: f32,
+    /// Biomes the structure appears in, empty for all of them
+    #[serde(default)]
+    pub biomes: Vec<String>,
+    /// Block name for every character used in `layers`
+    pub palette: HashMap<char, String>,
+    /// Horizontal slices from the bottom, rows along z and characters along
+    /// x, spaces leave the world untouched
+    pub layers: Vec<Vec<String>>,
+    /// Template position put on the first air block above the surface
+    #[serde(default)]
+    pub origin: (i32, i32, i32),
+}
+
+/// Template with resolved blocks, positions relative to the origin
+#[derive(Debug, Clone)]
+pub struct StructureTemplate {
+    pub name: String,
+    pub chance: f32,
+    pub biomes: Vec<String>,
+    blocks: Vec<(IVec3, BlockId)>,
+    min: IVec3,
+    max: IVec3,
+}
+
+impl StructureTemplate {
+    pub fn from_config(config: &StructureConfig, registry: &BlockRegistry) -> anyhow::Result<Self> {
+        ensure!(
+            (0.0..=1.0).contains(&config.chance),
+            "chance {} out of range",
+            config.chance
+        );
+        let mut palette = HashMap::new();
+        for (&key, name) in &config.palette {
+            palette.insert(key, registry.lookup(name)?);
+        }
+        let (ox, oy, oz) = config.origin;
+        let mut blocks = Vec::new();
+        for (y, layer) in config.layers.iter().enumerate() {
+            for (z, row) in layer.iter().enumerate() {
+                for (x, key) in row.chars().enumerate() {
+                    if key == ' ' {
+                        continue;
+                    }
+                    let block = match palette.get(&key) {
+                        Some(&block) => block,
+                        None => bail!("character {:?} missing from palette", key),
+                    };
+                    let pos = IVec3::new(x as i32 - ox, y as i32 - oy, z as i32 - oz);
+                    blocks.push((pos, block));
+                }
+            }
+        }
+        ensure!(!blocks.is_empty(), "empty template");
+        let min = blocks
+            .iter()
+            .fold(blocks[0].0, |acc, (pos, _)| acc.min(*pos));
+        let max = blocks
+            .iter()
+            .fold(blocks[0].0, |acc, (pos, _)| acc.max(*pos));
+        Ok(Self {
+            name: config.name.clone(),
+            chance: config.chance,
+            biomes: config.biomes.clone(),
+            blocks,
+            min,
+            max,
+        })
+    }
+}
+
+/// Structure picked for a chunk column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    pub template: usize,
+    pub origin: IVec3,
+}
+
+/// Places structures on top of generated terrain
+///
+/// Every chunk column gets at most one structure, picked and positioned from
+/// the seed and the generator surface height alone. Structures overlapping
+/// one rooted in a lower column are left out, so every block belongs to a
+/// single structure and only replacing air makes the result independent of
+/// generation order.
+#[derive(Debug, Clone, Default)]
+pub struct StructureSet {
+    seed: u32,
+    templates: Vec<StructureTemplate>,
+    /// Horizontal template extent in chunks
+    reach: i32,
+}
+
+impl StructureSet {
+    pub fn new(seed: u32, templates: Vec<StructureTemplate>) -> anyhow::Result<Self> {
+        let total: f32 = templates.iter().map(|template| template.chance).sum();
+        ensure!(total <= 1.0, "structure chances add up to {}", total);
+        let extent = templates
+            .iter()
+            .map(|template| {
+                let size = template.max.max(-template.min);
+                size.x.max(size.z)
+            })
+            .max()
+            .unwrap_or(0);
+        Ok(Self {
+            seed,
+            templates,
+            reach: (extent + Chunk::WIDTH as i32 - 1) / Chunk::WIDTH as i32,
+        })
+    }
+
+    pub fn templates(&self) -> &[StructureTemplate] {
+        &self.templates
+    }
+
+    /// Structure rolled for the chunk column, before overlaps are resolved
+    fn candidate(&self, column: (i32, i32), generator: &dyn Generator) -> Option<Placement> {
+        let (cx, cz) = column;
+        let roll = column_hash(self.seed.wrapping_add(11), cx, cz) as f32 / u32::MAX as f32;
+        let mut total = 0.0;
+        let template = self.templates.iter().position(|template| {
+            total += template.chance;
+            roll < total
+        })?;
+        let offset = column_hash(self.seed.wrapping_add(12), cx, cz) as usize;
+        let width = Chunk::WIDTH as i32;
+        let x = cx * width + (offset % Chunk::WIDTH) as i32;
+        let z = cz * width + (offset / Chunk::WIDTH % Chunk::WIDTH) as i32;
+        let biomes = &self.templates[template].biomes;
+        if !biomes.is_empty() {
+            let biome = generator.biome_at(x, z)?;
+            if !biomes.contains(&biome.name) {
+                return None;
+            }
+        }
+        let y = generator.surface_height(x, z)?;
+        Some(Placement {
+            template,
+            origin: IVec3::new(x, y, z),
+        })
+    }
+
+    /// Inclusive bounds in world coordinates
+    fn bounds(&self, placement: Placement) -> (IVec3, IVec3) {
+        let template = &self.templates[placement.template];
+        (
+            placement.origin + template.min,
+            placement.origin + template.max,
+        )
+    }
+
+    /// Candidate overlaps one rolled for a lower column
+    fn is_overlapped(
+        &self,
+        column: (i32, i32),
+        placement: Placement,
+        generator: &dyn Generator,
+    ) -> bool {
+        let (low, high) = self.bounds(placement);
+        // origins of overlapping structures are less than two extents apart
+        let range = 2 * self.reach + 1;
+        let (cx, cz) = column;
+        itertools::iproduct!(cx - range..=cx, cz - range..=cz + range)
+            .filter(|&other| other < column)
+            .filter_map(|other| self.candidate(other, generator))
+            .any(|other| {
+                let (other_low, other_high) = self.bounds(other);
+                low.cmple(other_high).all() && other_low.cmple(high).all()
+            })
+    }
+
+    /// Structure rooted in the chunk column, if any
+    pub fn placement(&self, column: (i32, i32), generator: &dyn Generator) -> Option<Placement> {
+        let placement = self.candidate(column, generator)?;
+        if self.is_overlapped(column, placement, generator) {
+            return None;
+        }
+        Some(placement)
+    }
+
+    /// Structure whose origin lies in the chunk, every structure has exactly
+    /// one such chunk
+    ///
+    /// Origins above or below the world belong to the top or bottom section.
+    pub fn rooted(&self, pos: ChunkPos, generator: &dyn Generator) -> Option<Placement> {
+        if self.templates.is_empty() {
+            return None;
+        }
+        let column = (pos.0, pos.2);
+        let placement = self.candidate(column, generator)?;
+        let section = placement
+            .origin
+            .y
+            .div_euclid(Chunk::HEIGHT as i32)
+            .clamp(0, ChunkPos::SECTIONS - 1);
+        if section != pos.1 || self.is_overlapped(column, placement, generator) {
+            return None;
+        }
+        Some(placement)
+    }
+
+    /// Blocks of the structure in world coordinates
+    pub fn blocks(&self, placement: Placement) -> impl '_ + Iterator<Item = (IVec3, BlockId)> {
+        self.templates[placement.template]
+            .blocks
+            .iter()
+            .map(move |&(offset, block)| (placement.origin + offset, block))
+    }
+}
+
+impl Map {
+    /// Write a structure rooted in a freshly generated chunk
+    ///
+    /// Every block is held for its chunk until the chunk is stashed and also
+    /// written right away when the chunk is loaded, through
+    /// [`Map::set_block`]. Chunks generated or restored later pick their
+    /// blocks up, a chunk unloaded without keeping it gets them back when
+    /// generated again.
+    pub(super) fn place_structure(&mut self, placement: Placement) {
+        let blocks: Vec<(IVec3, BlockId)> = self.queue.structures().blocks(placement).collect();
+        for (world_pos, block) in blocks {
+            let (chunk_pos, sub_pos) = match convert_pos(world_pos) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            self.held
+                .entry(chunk_pos)
+                .or_default()
+                .insert(sub_pos, block);
+            if self.block_at(world_pos) == Some(None) {
+                self.set_block(world_pos, Some(block));
+            }
+        }
+    }
+
+    /// Write structure blocks held for the chunk, only air is replaced
+    pub(super) fn place_held(&self, pos: ChunkPos, chunk: &mut Chunk) {
+        for (&sub_pos, &block) in self.held.get(&pos).into_iter().flatten() {
+            if chunk[sub_pos].is_none() {
+                chunk.set(sub_pos, Some(block));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{
+        block::test_registry,
+        generator::flat::{FlatGenerator, Span},
+        storage::EncodedChunk,
+        Map,
+    };
+
+    fn tree(registry: &BlockRegistry) -> StructureTemplate {
+        let config: StructureConfig = ron::from_str(
+            r#"(
+                name: "tree",
+                chance: 0.5,
+                palette: {'w': "wood", 'l': "green"},
+                layers: [
+                    ["", "", "  w"], ["", "", "  w"], ["", "", "  w"],
+                    ["lllll", "lllll", "llwll", "lllll", "lllll"],
+                    [" lll ", "lllll", "lllll", "lllll", " lll "],
+                ],
+                origin: (2, 0, 2),
+            )"#,
+        )
+        .unwrap();
+        StructureTemplate::from_config(&config, registry).unwrap()
+    }
+
+    #[test]
+    fn test_template() {
+        let registry = test_registry();
+        let tree = tree(&registry);
+        assert_eq!(tree.min, IVec3::new(-2, 0, -2));
+        assert_eq!(tree.max, IVec3::new(2, 4, 2));
+        assert_eq!(tree.blocks.len(), 3 + 25 + 21);
+        assert!(tree
+            .blocks
+            .contains(&(IVec3::ZERO, registry.lookup("wood").unwrap())));
+    }
+
+    #[test]
+    fn test_order_independent() {
+        let registry = test_registry();
+        let structures = StructureSet::new(3, vec![tree(&registry)]).unwrap();
+        let map = || {
+            let ground = Span(registry.lookup("dirt").ok(), 14);
+            Map::new(FlatGenerator::new(&[ground])).with_structures(structures.clone())
+        };
+        let positions: Vec<ChunkPos> = ChunkPos(0, 0, 0).iter_range(2).collect();
+        let (mut forward, mut backward, mut reloaded) = (map(), map(), map());
+        for &pos in &positions {
+            forward.load_chunk(pos);
+            reloaded.load_chunk(pos);
+        }
+        for &pos in positions.iter().rev() {
+            backward.load_chunk(pos);
+        }
+        // structures rooted in later chunks reach into loaded ones
+        assert!(!forward.take_changes().is_empty());
+        // dropped chunks get blocks from their neighbours back
+        for &pos in positions.iter().step_by(2) {
+            reloaded.unload_chunk(pos, false);
+        }
+        for &pos in positions.iter().step_by(2) {
+            reloaded.load_chunk(pos);
+        }
+        let wood = registry.lookup("wood").ok();
+        let mut trunks = 0;
+        for &pos in &positions {
+            let expected = EncodedChunk::encode(&forward[pos]);
+            assert_eq!(
+                expected,
+                EncodedChunk::encode(&backward[pos]),
+                "chunk {} differs",
+                pos
+            );
+            assert_eq!(
+                expected,
+                EncodedChunk::encode(&reloaded[pos]),
+                "reloaded chunk {} differs",
+                pos
+            );
+            trunks += forward[pos].iter().filter(|&(_, &blk)| blk == wood).count();
+        }
+        assert!(trunks > 0, "no structure placed");
+        // the flat surface is at y = 14, so every tree spans two sections
+        let spanning = positions
+            .iter()
+            .filter(|pos| pos.1 == 1)
+            .any(|&pos| forward[pos].iter().any(|(_, blk)| blk.is_some()));
+        assert!(spanning, "no structure crosses a chunk boundary");
+    }
+
+    #[test]
+    fn test_no_overlap() {
+        let registry = test_registry();
+        let tree = tree(&registry);
+        let structures = StructureSet::new(7, vec![tree.clone(), tree]).unwrap();
+        let generator = FlatGenerator::new(&[Span(registry.lookup("dirt").ok(), 14)]);
+        let placements: Vec<Placement> = itertools::iproduct!(-4..4, -4..4)
+            .filter_map(|column| structures.placement(column, &generator))
+            .collect();
+        assert!(placements.len() > 4);
+        for (i, &a) in placements.iter().enumerate() {
+            let (a_low, a_high) = structures.bounds(a);
+            for &b in &placements[i + 1..] {
+                let (b_low, b_high) = structures.bounds(b);
+                assert!(
+                    !(a_low.cmple(b_high).all() && b_low.cmple(a_high).all()),
+                    "{:?} overlaps {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+}
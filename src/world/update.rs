@@ -0,0 +1,265 @@
+use std::collections::{BTreeMap, HashSet};
+
+use glam::IVec3;
+
+use super::{
+    biome::mix,
+    block::{BlockId, BlockRegistry, RandomTick},
+    chunk::{BlockSubPos, Chunk},
+    convert_pos, ChunkPos, Map,
+};
+
+/// Block edit made through [`Map::set_block`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockChanged {
+    pub pos: IVec3,
+    pub old: Option<BlockId>,
+    pub new: Option<BlockId>,
+}
+
+/// Blocks waiting for a tick
+///
+/// A block is only queued once, scheduling it again before it is due keeps
+/// the earlier tick.
+#[derive(Debug, Default)]
+pub struct BlockTicks {
+    tick: u64,
+    due: BTreeMap<u64, Vec<IVec3>>,
+    scheduled: HashSet<IVec3>,
+}
+
+impl BlockTicks {
+    /// Tick the block `delay` ticks from now, at least on the next one
+    pub fn schedule(&mut self, pos: IVec3, delay: u32) {
+        if self.scheduled.insert(pos) {
+            let tick = self.tick + delay.max(1) as u64;
+            self.due.entry(tick).or_default().push(pos);
+        }
+    }
+
+    pub fn is_scheduled(&self, pos: IVec3) -> bool {
+        self.scheduled.contains(&pos)
+    }
+
+    /// Move to the next tick, returns the blocks due on it
+    pub fn advance(&mut self) -> Vec<IVec3> {
+        self.tick += 1;
+        let due = self.due.remove(&self.tick).unwrap_or_default();
+        for pos in &due {
+            self.scheduled.remove(pos);
+        }
+        due
+    }
+}
+
+/// Seeded random source for block ticks (splitmix64)
+#[derive(Debug, Clone)]
+pub struct TickRng(u64);
+
+impl TickRng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        mix(self.0)
+    }
+
+    /// Uniform in `0..bound`
+    pub fn below(&mut self, bound: u32) -> u32 {
+        (((self.next_u64() >> 32) * bound as u64) >> 32) as u32
+    }
+
+    /// Uniform in `-radius..=radius`
+    fn offset(&mut self, radius: i32) -> i32 {
+        self.below(2 * radius as u32 + 1) as i32 - radius
+    }
+}
+
+impl Map {
+    /// Replace a block and record the change, returns the previous block or
+    /// `None` when the chunk isn't loaded
+    ///
+    /// Changes are collected until [`Map::take_changes`], the block update
+    /// system turns them into [`BlockChanged`] events.
+    pub fn set_block(&mut self, pos: IVec3, block: Option<BlockId>) -> Option<Option<BlockId>> {
+        let (chunk_pos, sub_pos) = convert_pos(pos)?;
+        let chunk = self.get_mut(chunk_pos)?;
+        let old = chunk.set(sub_pos, block);
+        if old != block {
+            self.changes.push(BlockChanged {
+                pos,
+                old,
+                new: block,
+            });
+        }
+        Some(old)
+    }
+
+    /// Changes made since the last call, oldest first
+    pub fn take_changes(&mut self) -> Vec<BlockChanged> {
+        std::mem::take(&mut self.changes)
+    }
+
+    /// Tick `per_chunk` random blocks in every loaded chunk
+    ///
+    /// Chunks are visited in position order, so the same seed gives the same
+    /// result.
+    pub fn random_ticks(&mut self, registry: &BlockRegistry, per_chunk: u32, rng: &mut TickRng) {
+        let mut chunks: Vec<ChunkPos> = self
+            .iter()
+            // nothing ever happens in empty sections
+            .filter(|(_, chunk)| {
+                !(chunk.is_uniform() && chunk[BlockSubPos::new(0, 0, 0)].is_none())
+            })
+            .map(|(pos, _)| pos)
+            .collect();
+        chunks.sort();
+        for chunk_pos in chunks {
+            let origin = chunk_pos.origin();
+            for _ in 0..per_chunk {
+                let x = rng.below(Chunk::WIDTH as u32) as i32;
+                let y = rng.below(Chunk::HEIGHT as u32) as i32;
+                let z = rng.below(Chunk::WIDTH as u32) as i32;
+                self.random_tick(registry, origin + IVec3::new(x, y, z), rng);
+            }
+        }
+    }
+
+    /// Run the random tick handler of the block at `pos`
+    pub fn random_tick(&mut self, registry: &BlockRegistry, pos: IVec3, rng: &mut TickRng) {
+        let block = match self.block_at(pos) {
+            Some(Some(block)) => block,
+            _ => return,
+        };
+        let lookup = |name: &str| registry.find(name);
+        match &registry[block].random_tick {
+            Some(RandomTick::Spread { onto }) => {
+                let target = pos + IVec3::new(rng.offset(1), rng.offset(1), rng.offset(1));
+                let covered = self.block_at(target + IVec3::Y) != Some(None);
+                if self.block_at(target) == Some(lookup(onto)) && !covered {
+                    self.set_block(target, Some(block));
+                }
+            }
+            Some(RandomTick::Grow { into }) => {
+                self.set_block(pos, lookup(into));
+            }
+            Some(RandomTick::Decay {
+                into,
+                support,
+                radius,
+            }) => {
+                let support = lookup(support);
+                let radius = *radius;
+                let supported = (-radius..=radius).any(|x| {
+                    (-radius..=radius).any(|y| {
+                        (-radius..=radius)
+                            .any(|z| self.block_at(pos + IVec3::new(x, y, z)) == Some(support))
+                    })
+                });
+                if !supported {
+                    self.set_block(pos, into.as_deref().and_then(lookup));
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{
+        block::test_registry,
+        generator::flat::{FlatGenerator, Span},
+        storage::EncodedChunk,
+    };
+
+    #[test]
+    fn test_set_block() {
+        let registry = test_registry();
+        let stone = registry.lookup("stone").ok();
+        let mut map = Map::new(FlatGenerator::new(&[]));
+        map.load_chunk(ChunkPos(0, 0, 0));
+        let pos = IVec3::new(3, 4, 5);
+        assert_eq!(map.set_block(pos, stone), Some(None));
+        assert_eq!(map.set_block(pos, stone), Some(stone));
+        assert_eq!(map.set_block(IVec3::new(16, 0, 0), stone), None);
+        assert_eq!(map.set_block(pos, None), Some(stone));
+        // writing the same block again doesn't cost a remesh
+        let revision = map[ChunkPos(0, 0, 0)].revision();
+        assert_eq!(map.set_block(pos, None), Some(None));
+        assert_eq!(map[ChunkPos(0, 0, 0)].revision(), revision);
+        let changes = map.take_changes();
+        assert_eq!(
+            changes,
+            vec![
+                BlockChanged {
+                    pos,
+                    old: None,
+                    new: stone
+                },
+                BlockChanged {
+                    pos,
+                    old: stone,
+                    new: None
+                },
+            ]
+        );
+        assert!(map.take_changes().is_empty());
+    }
+
+    #[test]
+    fn test_random_ticks() {
+        let registry = test_registry();
+        let block = |name| registry.lookup(name).ok();
+        let map = || {
+            let ground = [Span(block("dirt"), 4)];
+            let mut map = Map::new(FlatGenerator::new(&ground));
+            for pos in ChunkPos(0, 0, 0).iter_range(1) {
+                map.load_chunk(pos);
+            }
+            map.set_block(IVec3::new(8, 3, 8), block("grass"));
+            map.set_block(IVec3::new(4, 4, 4), block("wheat_0"));
+            map
+        };
+        let run = |map: &mut Map| {
+            let mut rng = TickRng::new(7);
+            for _ in 0..1000 {
+                map.random_ticks(&registry, 64, &mut rng);
+            }
+        };
+        let (mut first, mut second) = (map(), map());
+        run(&mut first);
+        run(&mut second);
+        for (pos, chunk) in first.iter() {
+            assert_eq!(
+                EncodedChunk::encode(chunk),
+                EncodedChunk::encode(&second[pos]),
+                "chunk {} differs",
+                pos
+            );
+        }
+        let grass = first[ChunkPos(0, 0, 0)]
+            .iter()
+            .filter(|(_, &blk)| blk == block("grass"))
+            .count();
+        assert!(grass > 1, "grass didn't spread");
+        assert_eq!(first.block_at(IVec3::new(4, 4, 4)), Some(block("wheat_2")));
+    }
+
+    #[test]
+    fn test_ticks() {
+        let mut ticks = BlockTicks::default();
+        let (a, b) = (IVec3::new(0, 1, 0), IVec3::new(2, 3, 4));
+        ticks.schedule(a, 2);
+        ticks.schedule(b, 0);
+        ticks.schedule(a, 1);
+        assert!(ticks.is_scheduled(a));
+        assert_eq!(ticks.advance(), vec![b]);
+        assert_eq!(ticks.advance(), vec![a]);
+        assert!(!ticks.is_scheduled(a));
+        assert!(ticks.advance().is_empty());
+    }
+}